@@ -4,7 +4,7 @@ use std::{fs::File, time::Duration};
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 use tempfile::tempdir;
 
-use ciso_rs::{compress_ciso, decompress_ciso};
+use ciso_rs::{DecompressOptions, compress_ciso, decompress_ciso, decompress_ciso_with};
 
 const BLOCK_SIZE: usize = 2048;
 const ISO_SIZE: usize = 128 * 1024 * 1024; // 128 MiB
@@ -61,6 +61,19 @@ fn bench_ciso(c: &mut Criterion) {
         });
     });
 
+    // Same as above without the worker pool, to compare both pipelines
+    group.bench_function(BenchmarkId::new("decompress", "single_thread"), |b| {
+        let options = DecompressOptions::new().threads(1);
+        b.iter(|| {
+            decompress_ciso_with(
+                File::open(&cso_path).unwrap(),
+                File::create(&out_path).unwrap(),
+                &options,
+            )
+            .unwrap();
+        });
+    });
+
     group.finish();
 }
 
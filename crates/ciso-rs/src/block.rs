@@ -0,0 +1,95 @@
+use std::io;
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::error::CisoError;
+use crate::index::{BlockCodec, CisoIndex};
+use crate::{lz4, lzo};
+
+/// Decompresses block `i` into `out`, resized to the block's decompressed size, returning the
+/// size of its stored stream without the alignment padding following it.
+///
+/// `read_exact_at` reads the raw bytes stored at a given file offset.
+#[expect(clippy::cast_possible_truncation)]
+pub(crate) fn decode_block(
+    index: &CisoIndex,
+    i: usize,
+    in_buf: &mut Vec<u8>,
+    out: &mut Vec<u8>,
+    mut read_exact_at: impl FnMut(u64, &mut [u8]) -> io::Result<()>,
+) -> Result<usize, CisoError> {
+    let block_size = u64::from(index.header().block_size);
+    let range = index.block_range(i);
+    let expected_size = (range.end - range.start) as usize;
+
+    let codec = index.codec(i);
+    let off = index.offset(i);
+
+    out.resize(expected_size, 0);
+
+    if codec == BlockCodec::Plain {
+        read_exact_at(off, out)?;
+        return Ok(out.len());
+    }
+
+    let next = index.offset(i + 1);
+    let size = next.checked_sub(off).ok_or(CisoError::NonMonotonicIndex {
+        block: i + 1,
+        offset: next,
+        prev_offset: off,
+    })?;
+    // Streams never exceed twice the block size, the rest can only be alignment padding
+    let size = size.min(block_size * 2) as usize;
+
+    if size > in_buf.len() {
+        in_buf.resize(size, 0);
+    }
+    read_exact_at(off, &mut in_buf[..size])?;
+
+    decode_stored(codec, &in_buf[..size], out, i, off)
+}
+
+/// Decompresses the `stored` bytes of block `i`, found at `offset`, filling `out` exactly.
+///
+/// Returns the size of the stream at the start of `stored`, whatever follows it being ignored.
+#[expect(clippy::cast_possible_truncation)]
+pub(crate) fn decode_stored(
+    codec: BlockCodec,
+    stored: &[u8],
+    out: &mut [u8],
+    i: usize,
+    offset: u64,
+) -> Result<usize, CisoError> {
+    let corrupt = CisoError::CorruptBlock { block: i, offset };
+
+    let (total_in, total_out) = match codec {
+        BlockCodec::Plain => {
+            let data = stored.get(..out.len()).ok_or(corrupt)?;
+            out.copy_from_slice(data);
+            return Ok(out.len());
+        }
+        BlockCodec::Deflate | BlockCodec::Zlib => {
+            let mut decomp = Decompress::new(codec == BlockCodec::Zlib);
+            let status = decomp
+                .decompress(stored, out, FlushDecompress::Finish)
+                .map_err(|_| CisoError::CorruptBlock { block: i, offset })?;
+
+            if status != Status::StreamEnd {
+                return Err(corrupt);
+            }
+            (decomp.total_in() as usize, decomp.total_out() as usize)
+        }
+        BlockCodec::Lz4 => lz4::decompress(stored, out).ok_or(corrupt)?,
+        BlockCodec::Lzo => lzo::decompress(stored, out).ok_or(corrupt)?,
+    };
+
+    if total_out != out.len() {
+        return Err(CisoError::SizeMismatch {
+            block: i,
+            expected: out.len() as u64,
+            actual: total_out as u64,
+        });
+    }
+
+    Ok(total_in)
+}
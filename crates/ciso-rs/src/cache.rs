@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCapacity {
+    Blocks(usize),
+    Bytes(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub capacity: CacheCapacity,
+    pub read_ahead: usize, // Blocks decoded ahead of sequential reads, 0 = disabled
+}
+
+impl CacheConfig {
+    #[must_use]
+    pub fn blocks(count: usize) -> Self {
+        Self {
+            capacity: CacheCapacity::Blocks(count),
+            read_ahead: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn bytes(size: u64) -> Self {
+        Self {
+            capacity: CacheCapacity::Bytes(size),
+            read_ahead: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks;
+        self
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    pub(crate) fn capacity_in_blocks(&self, block_size: u32) -> usize {
+        match self.capacity {
+            CacheCapacity::Blocks(count) => count.max(1),
+            CacheCapacity::Bytes(size) => ((size / u64::from(block_size)) as usize).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// LRU cache of decompressed blocks, shared between readers.
+pub(crate) struct BlockCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Lru {
+    tick: u64,
+    blocks: HashMap<usize, (Arc<[u8]>, u64)>,
+    by_age: BTreeMap<u64, usize>, // Last use tick → block index
+}
+
+impl BlockCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Looks a block up, counting the hit or miss.
+    pub(crate) fn get(&self, block: usize) -> Option<Arc<[u8]>> {
+        let mut lru = self.lru.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+
+        let Some((data, age)) = lru.blocks.get_mut(&block) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let data = data.clone();
+        let prev = *age;
+        *age = tick;
+        lru.by_age.remove(&prev);
+        lru.by_age.insert(tick, block);
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(data)
+    }
+
+    pub(crate) fn contains(&self, block: usize) -> bool {
+        self.lru.lock().blocks.contains_key(&block)
+    }
+
+    pub(crate) fn insert(&self, block: usize, data: Arc<[u8]>) {
+        let mut lru = self.lru.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+
+        if let Some((_, prev)) = lru.blocks.insert(block, (data, tick)) {
+            lru.by_age.remove(&prev);
+        }
+        lru.by_age.insert(tick, block);
+
+        while lru.blocks.len() > self.capacity {
+            let Some((_, oldest)) = lru.by_age.pop_first() else {
+                break;
+            };
+            lru.blocks.remove(&oldest);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
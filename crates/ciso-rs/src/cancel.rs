@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::CisoError;
+
+/// Shared flag asking a running operation to stop as soon as possible.
+///
+/// Clones observe the same flag, so one can be handed to an operation's options
+/// while another is cancelled from a signal handler or a UI thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), CisoError> {
+        if self.is_cancelled() {
+            return Err(CisoError::Cancelled);
+        }
+
+        Ok(())
+    }
+}
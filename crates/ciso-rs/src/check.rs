@@ -1,122 +1,484 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
-use std::mem;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
-use flate2::{Decompress, FlushDecompress};
+use memmap2::Mmap;
 
+use crate::block::decode_stored;
+use crate::cancel::CancellationToken;
 use crate::ciso_header::CisoHeader;
+use crate::error::CisoError;
+use crate::index::{BlockCodec, CisoIndex};
+use crate::progress::{Observer, Progress, ProgressObserver};
+use crate::queue::{BoundedQueue, PARALLEL_MIN_BLOCKS};
 
-#[expect(clippy::cast_possible_truncation)]
-pub fn check_ciso(mut file: File, full: bool) -> io::Result<()> {
-    let file_len = file.seek(SeekFrom::End(0))?;
-    file.seek(SeekFrom::Start(0))?;
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    pub(crate) full: bool,
+    threads: Option<usize>, // Defaults to the number of CPUs
+    pub(crate) progress: Observer,
+    pub(crate) cancel: CancellationToken,
+}
 
-    let header = CisoHeader::read_from(&mut file)?;
-    let block_size = header.block_size as usize;
-    let total_blocks = (header.total_bytes as usize).div_ceil(block_size);
+impl CheckOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    let index_pos = mem::size_of::<CisoHeader>() as u64;
-    file.seek(SeekFrom::Start(index_pos))?;
+    /// Also decompress every compressed block to validate the deflate or LZ4 streams.
+    #[must_use]
+    pub fn full(mut self, full: bool) -> Self {
+        self.full = full;
+        self
+    }
 
-    let mut index = vec![0u32; total_blocks + 1];
-    for v in &mut index {
-        let mut buf = [0u8; 4];
-        file.read_exact(&mut buf)?;
-        *v = u32::from_le_bytes(buf);
+    /// Number of threads inflating the blocks of a full check.
+    #[must_use]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
     }
 
-    let data_start = index_pos + index.len() as u64 * 4;
+    /// Observer notified each time a block has been checked.
+    #[must_use]
+    pub fn progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Observer::new(observer);
+        self
+    }
 
-    let end_off = u64::from(index[total_blocks] & 0x7fff_ffff) << header.align;
-    if end_off > file_len {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "index end exceeds file size",
-        ));
+    /// Token making the check stop with `CisoError::Cancelled` once cancelled.
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
     }
 
-    let mut prev_off = data_start;
+    pub fn validate(&self) -> Result<(), CisoError> {
+        if let Some(n @ 0) = self.threads {
+            return Err(CisoError::InvalidOption {
+                name: "threads",
+                value: n as u64,
+            });
+        }
 
-    for i in 0..total_blocks {
-        let raw = index[i];
-        let plain = raw & 0x8000_0000 != 0;
+        Ok(())
+    }
+}
+
+pub fn check_ciso(file: File, full: bool) -> Result<(), CisoError> {
+    check_ciso_with(file, &CheckOptions::new().full(full))
+}
 
-        let off = u64::from(raw & 0x7fff_ffff) << header.align;
-        let next = u64::from(index[i + 1] & 0x7fff_ffff) << header.align;
+/// Outcome of a check going through every block, see `check_ciso_report`.
+#[derive(Debug)]
+pub struct CheckReport {
+    pub header: CisoHeader,
+    pub total_blocks: usize,
+    pub plain_blocks: usize,
+    pub compressed_blocks: usize,
+    pub file_len: u64,
+    pub trailing_bytes: u64, // Bytes after the end of the final block
+    pub faulty_blocks: Vec<FaultyBlock>,
+}
 
-        if off < data_start {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("block {i}: offset before data start"),
-            ));
+impl CheckReport {
+    /// Size of the CSO file relative to the image size, 1.0 for empty images.
+    #[must_use]
+    #[expect(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        if self.header.total_bytes == 0 {
+            return 1.0;
         }
 
-        if off < prev_off {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("non-monotonic index at block {i}"),
-            ));
+        self.file_len as f64 / self.header.total_bytes as f64
+    }
+
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.faulty_blocks.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct FaultyBlock {
+    pub block: usize,
+    pub range: Range<u64>, // Bytes of the decompressed image held by the block
+    pub error: CisoError,
+}
+
+/// Header and index of the checked file.
+struct Layout {
+    header: CisoHeader,
+    index: CisoIndex,
+    file_len: u64,
+    data_start: u64,
+}
+
+impl Layout {
+    fn read_from(file: &mut (impl Read + Seek)) -> Result<Self, CisoError> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let header = CisoHeader::read_from(file)?;
+        let index = CisoIndex::read_from(file, &header, file_len)?;
+        let data_start = index.data_start();
+
+        Ok(Self {
+            header,
+            index,
+            file_len,
+            data_start,
+        })
+    }
+
+    /// Largest stored size of a valid compressed block, alignment padding included.
+    fn max_stored_size(&self) -> u64 {
+        u64::from(self.header.block_size) * 2 + (1u64 << self.header.align) - 1
+    }
+}
+
+/// Block which passed the structural checks.
+struct BlockSpan {
+    block: usize,
+    offset: u64,
+    size: u64, // Stored size, without the padding of plain blocks
+    expected_size: usize,
+    codec: BlockCodec,
+}
+
+pub fn check_ciso_with(mut file: File, options: &CheckOptions) -> Result<(), CisoError> {
+    options.validate()?;
+
+    let layout = Layout::read_from(&mut file)?;
+    let (spans, structural) = leading_spans(&layout)?;
+
+    verify_blocks(&file, &layout, spans, options, None)?;
+
+    structural.map_or(Ok(()), Err)
+}
+
+/// Checks a CSO stream, read from its start.
+///
+/// Unlike `check_ciso_with`, which maps files and inflates them on a worker pool, the blocks
+/// of a full check are read and inflated in order on the calling thread: `threads` is ignored.
+pub fn check_ciso_stream(
+    mut input: impl Read + Seek,
+    options: &CheckOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
+
+    let layout = Layout::read_from(&mut input)?;
+    let (spans, structural) = leading_spans(&layout)?;
+
+    let max_size = layout.max_stored_size();
+    let mut in_buf = Vec::new();
+    let mut out = Vec::with_capacity(layout.header.block_size as usize);
+    walk_blocks(&spans, &layout, options, None, |_, span| {
+        check_stored_size(span, max_size)?;
+
+        #[expect(clippy::cast_possible_truncation)] // At most twice the block size
+        in_buf.resize(span.size as usize, 0);
+        input.seek(SeekFrom::Start(span.offset))?;
+        input.read_exact(&mut in_buf)?;
+
+        decode_span(&in_buf, span, &mut out)
+    })?;
+
+    structural.map_or(Ok(()), Err)
+}
+
+/// Spans of the blocks up to the first structural error, returned along with them.
+///
+/// Blocks are only inflated up to that error, which is reported last.
+fn leading_spans(layout: &Layout) -> Result<(Vec<BlockSpan>, Option<CisoError>), CisoError> {
+    let total_blocks = layout.index.len();
+
+    let end_off = layout.index.offset(total_blocks);
+    if end_off > layout.file_len {
+        return Err(CisoError::IndexOutOfBounds {
+            block: total_blocks,
+            offset: end_off,
+            start: layout.data_start,
+            end: layout.file_len,
+        });
+    }
+
+    let mut spans = Vec::with_capacity(total_blocks);
+    let mut prev_off = layout.data_start;
+    for i in 0..total_blocks {
+        match block_span(layout, i, prev_off) {
+            Ok(span) => {
+                prev_off = span.offset;
+                spans.push(span);
+            }
+            Err(err) => return Ok((spans, Some(err))),
         }
+    }
 
-        let expected_size = if i + 1 == total_blocks {
-            header.total_bytes as usize - i * block_size
-        } else {
-            block_size
-        };
-
-        let size = if plain {
-            expected_size as u64
-        } else {
-            next.checked_sub(off).ok_or_else(|| {
-                io::Error::new(io::ErrorKind::InvalidData, "negative compressed size")
-            })?
-        };
-
-        if plain && next - off != expected_size as u64 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("invalid plain block size at {i}"),
-            ));
+    Ok((spans, None))
+}
+
+/// Checks every block instead of stopping at the first faulty one, which are all listed in the report.
+///
+/// Only an unreadable header or index, and cancellations, are returned as errors.
+pub fn check_ciso_report(mut file: File, options: &CheckOptions) -> Result<CheckReport, CisoError> {
+    options.validate()?;
+
+    let layout = Layout::read_from(&mut file)?;
+    let total_blocks = layout.index.len();
+
+    let mut spans = Vec::with_capacity(total_blocks);
+    let mut faulty_blocks = Vec::new();
+    let mut prev_off = layout.data_start;
+    for i in 0..total_blocks {
+        match block_span(&layout, i, prev_off) {
+            Ok(span) => {
+                prev_off = span.offset;
+                spans.push(span);
+            }
+            Err(error) => faulty_blocks.push(FaultyBlock {
+                block: i,
+                range: layout.index.block_range(i),
+                error,
+            }),
         }
+    }
+
+    verify_blocks(&file, &layout, spans, options, Some(&mut faulty_blocks))?;
+    faulty_blocks.sort_by_key(|faulty| faulty.block);
+
+    let plain_blocks = layout.index.iter().filter(|entry| entry.is_plain).count();
+
+    Ok(CheckReport {
+        header: layout.header,
+        total_blocks,
+        plain_blocks,
+        compressed_blocks: total_blocks - plain_blocks,
+        file_len: layout.file_len,
+        trailing_bytes: layout
+            .file_len
+            .saturating_sub(layout.index.offset(total_blocks)),
+        faulty_blocks,
+    })
+}
+
+/// Structural checks of block `i`, whose predecessor is stored at `prev_off`.
+#[expect(clippy::cast_possible_truncation)]
+fn block_span(layout: &Layout, i: usize, prev_off: u64) -> Result<BlockSpan, CisoError> {
+    let entry = layout.index.check_entry(i, prev_off, layout.file_len)?;
+    let range = layout.index.block_range(i);
 
-        if off + size > file_len {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("block {i} exceeds file size"),
-            ));
+    Ok(BlockSpan {
+        block: i,
+        offset: entry.offset,
+        size: entry.stored_len,
+        expected_size: (range.end - range.start) as usize,
+        codec: layout.index.codec(i),
+    })
+}
+
+/// Reports the progress over `spans`, inflating their compressed blocks in full mode.
+///
+/// Faulty blocks are collected in `faults` when given, otherwise the first one is returned.
+fn verify_blocks(
+    file: &File,
+    layout: &Layout,
+    spans: Vec<BlockSpan>,
+    options: &CheckOptions,
+    faults: Option<&mut Vec<FaultyBlock>>,
+) -> Result<(), CisoError> {
+    let max_size = layout.max_stored_size();
+    let threads = options.threads.unwrap_or_else(num_cpus::get).max(1);
+
+    if !options.full {
+        walk_blocks(&spans, layout, options, faults, |_, _| Ok(()))
+    } else if threads == 1 || spans.len() < PARALLEL_MIN_BLOCKS {
+        let mmap = unsafe { Mmap::map(file)? };
+        let mut out = Vec::with_capacity(layout.header.block_size as usize);
+        walk_blocks(&spans, layout, options, faults, |_, span| {
+            check_block(&mmap, span, max_size, &mut out)
+        })
+    } else {
+        check_parallel(file, layout, spans, max_size, threads, options, faults)
+    }
+}
+
+/// Goes through the blocks in order, calling `verify` on the compressed ones in full mode.
+fn walk_blocks(
+    spans: &[BlockSpan],
+    layout: &Layout,
+    options: &CheckOptions,
+    mut faults: Option<&mut Vec<FaultyBlock>>,
+    mut verify: impl FnMut(usize, &BlockSpan) -> Result<(), CisoError>,
+) -> Result<(), CisoError> {
+    let total_blocks = layout.index.len();
+    let mut bytes_in = 0;
+    let mut bytes_out = 0;
+
+    for (k, span) in spans.iter().enumerate() {
+        options.cancel.check()?;
+
+        if options.full
+            && span.codec != BlockCodec::Plain
+            && let Err(error) = verify(k, span)
+        {
+            match faults.as_deref_mut() {
+                // A cancellation says nothing about the block
+                Some(faults) if !matches!(error, CisoError::Cancelled) => {
+                    faults.push(FaultyBlock {
+                        block: span.block,
+                        range: layout.index.block_range(span.block),
+                        error,
+                    });
+                }
+                _ => return Err(error),
+            }
         }
 
-        if full && !plain {
-            if size > (block_size as u64 * 2) {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("compressed block {i} too large"),
-                ));
+        bytes_in += span.size;
+        bytes_out += span.expected_size as u64;
+
+        options.progress.report(&Progress {
+            blocks_done: span.block + 1,
+            total_blocks,
+            bytes_in,
+            bytes_out,
+        });
+    }
+
+    Ok(())
+}
+
+/// Inflates the compressed blocks on a pool of workers, the results being consumed in block order.
+fn check_parallel(
+    file: &File,
+    layout: &Layout,
+    spans: Vec<BlockSpan>,
+    max_size: u64,
+    threads: usize,
+    options: &CheckOptions,
+    faults: Option<&mut Vec<FaultyBlock>>,
+) -> Result<(), CisoError> {
+    let mmap = Arc::new(unsafe { Mmap::map(file)? });
+    let spans = Arc::new(spans);
+
+    // `None` tells a worker to stop
+    let jobs = BoundedQueue::<Option<usize>>::new(threads * 2);
+    let results = BoundedQueue::<(usize, Result<(), CisoError>)>::new(threads * 2);
+
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    {
+        let jobs = jobs.clone();
+        let results = results.clone();
+        let spans = spans.clone();
+        let cancel = options.cancel.clone();
+        handles.push(thread::spawn(move || {
+            for (k, span) in spans.iter().enumerate() {
+                if cancel.is_cancelled() {
+                    // Unblock the workers and the checker, which will report the cancellation
+                    jobs.close();
+                    results.close();
+                    return;
+                }
+                if span.codec != BlockCodec::Plain {
+                    jobs.push(Some(k));
+                }
             }
+            for _ in 0..threads {
+                jobs.push(None);
+            }
+        }));
+    }
 
-            file.seek(SeekFrom::Start(off))?;
-            let mut buf = vec![0u8; size as usize];
-            file.read_exact(&mut buf)?;
+    for _ in 0..threads {
+        let jobs = jobs.clone();
+        let results = results.clone();
+        let mmap = mmap.clone();
+        let spans = spans.clone();
 
-            let mut out = vec![0u8; expected_size];
-            let mut decomp = Decompress::new(false);
+        handles.push(thread::spawn(move || {
+            let mut out = Vec::new();
+            while let Some(Some(k)) = jobs.pop() {
+                let span = &spans[k];
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    check_block(&mmap, span, max_size, &mut out)
+                }))
+                .unwrap_or(Err(CisoError::WorkerPanicked {
+                    block: Some(span.block),
+                }));
 
-            decomp
-                .decompress(&buf, &mut out, FlushDecompress::Finish)
-                .map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidData, format!("invalid zlib at {i}"))
-                })?;
+                // Keep going after a failure, earlier blocks may still fail first
+                if !results.push((k, result)) {
+                    break;
+                }
+            }
+        }));
+    }
 
-            if decomp.total_out() as usize != expected_size {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("invalid decompressed size at {i}"),
-                ));
+    let mut pending = HashMap::with_capacity(results.capacity * 2);
+    let checked = walk_blocks(&spans, layout, options, faults, |k, _| {
+        loop {
+            if let Some(result) = pending.remove(&k) {
+                return result;
             }
+            // Queues are only closed early on cancellation
+            let Some((j, result)) = results.pop() else {
+                return Err(CisoError::Cancelled);
+            };
+            pending.insert(j, result);
         }
+    });
+
+    // Whatever happened, make sure no thread is left waiting on a queue
+    jobs.close();
+    results.close();
+    let mut panicked = false;
+    for handle in handles {
+        panicked |= handle.join().is_err();
+    }
 
-        prev_off = off;
+    checked?;
+    if panicked {
+        return Err(CisoError::WorkerPanicked { block: None });
     }
 
     Ok(())
 }
+
+#[expect(clippy::cast_possible_truncation)]
+fn check_block(
+    mmap: &[u8],
+    span: &BlockSpan,
+    max_size: u64,
+    out: &mut Vec<u8>,
+) -> Result<(), CisoError> {
+    check_stored_size(span, max_size)?;
+
+    let start = span.offset as usize;
+    decode_span(&mmap[start..start + span.size as usize], span, out)
+}
+
+/// Rejects compressed blocks larger than any valid stream, before reading them.
+fn check_stored_size(span: &BlockSpan, max_size: u64) -> Result<(), CisoError> {
+    if span.size > max_size {
+        return Err(CisoError::CorruptBlock {
+            block: span.block,
+            offset: span.offset,
+        });
+    }
+
+    Ok(())
+}
+
+/// Decompresses the `data` stored for a compressed block, which must fill it exactly.
+fn decode_span(data: &[u8], span: &BlockSpan, out: &mut Vec<u8>) -> Result<(), CisoError> {
+    out.resize(span.expected_size, 0);
+    decode_stored(span.codec, data, out, span.block, span.offset)?;
+    Ok(())
+}
@@ -1,55 +1,180 @@
 use std::io;
-use std::mem;
-use std::slice;
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+use crate::compress::MAX_BLOCK_SIZE;
+use crate::error::CisoError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CisoHeader {
-    pub magic: [u8; 4],   // 'C','I','S','O'
+    pub magic: [u8; 4],   // 'C','I','S','O', or 'Z','I','S','O' for ZSO
     pub header_size: u32, // == 0x18
     pub total_bytes: u64,
     pub block_size: u32, // 0x800
-    pub ver: u8,         // 0x01
+    pub ver: u8,         // 0x01, or 0x02 for CSO v2
     pub align: u8,
     pub rsv_06: [u8; 2],
 }
 
+/// Layout variant of a CSO file, given by the header's magic and version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CisoFormat {
+    /// Deflate blocks, the index high bit flagging plain ones.
+    #[default]
+    Cso,
+    /// Deflate or LZ4 blocks, the index high bit flagging LZ4 ones, plain blocks being those
+    /// stored on at least their decompressed size.
+    Cso2,
+    /// ZSO, with a `ZISO` magic: LZ4 blocks, the index high bit flagging plain ones.
+    Zso,
+}
+
 impl CisoHeader {
+    /// Size of the serialized header, the index starts right after it.
+    pub const SIZE: usize = 0x18;
+
     #[must_use]
-    #[expect(clippy::cast_possible_truncation)] // CisoHeader will always fit u32
     pub fn new(total_bytes: u64) -> Self {
+        Self::with_layout(total_bytes, 0x800, 0)
+    }
+
+    #[must_use]
+    pub fn with_layout(total_bytes: u64, block_size: u32, align: u8) -> Self {
+        Self::with_format(total_bytes, block_size, align, CisoFormat::Cso)
+    }
+
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)] // CisoHeader will always fit u32
+    pub fn with_format(total_bytes: u64, block_size: u32, align: u8, format: CisoFormat) -> Self {
         Self {
-            magic: *b"CISO",
-            header_size: mem::size_of::<CisoHeader>() as u32,
+            magic: match format {
+                CisoFormat::Cso | CisoFormat::Cso2 => *b"CISO",
+                CisoFormat::Zso => *b"ZISO",
+            },
+            header_size: Self::SIZE as u32,
             total_bytes,
-            block_size: 0x800,
-            ver: 0x01,
-            align: 0,
+            block_size,
+            ver: match format {
+                CisoFormat::Cso | CisoFormat::Zso => 0x01,
+                CisoFormat::Cso2 => 0x02,
+            },
+            align,
             rsv_06: [0; 2],
         }
     }
 
-    pub fn read_from(r: &mut impl io::Read) -> io::Result<Self> {
-        read_struct(r)
+    #[must_use]
+    pub fn format(&self) -> CisoFormat {
+        if self.magic == *b"ZISO" {
+            CisoFormat::Zso
+        } else if self.ver == 2 {
+            CisoFormat::Cso2
+        } else {
+            CisoFormat::Cso
+        }
+    }
+
+    /// Smallest index alignment shift able to address the largest possible output.
+    ///
+    /// Blocks are never stored larger than their plain size, so the output is bounded by
+    /// the header, the index, the input and one alignment padding per block.
+    #[must_use]
+    pub fn min_align(total_bytes: u64, block_size: u32) -> u8 {
+        // Computed on 128 bits so that no header, however large, can overflow it
+        let entries = u128::from(total_bytes.div_ceil(u64::from(block_size))) + 1;
+        let data_start = Self::SIZE as u128 + entries * 4;
+
+        (0..31)
+            .find(|&align| {
+                let padding = entries * ((1 << align) - 1);
+                (data_start + u128::from(total_bytes) + padding) >> align <= 0x7fff_ffff
+            })
+            .unwrap_or(31)
+    }
+
+    /// Parses the little-endian on-disk layout, without validating it.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            magic: field(bytes, 0x00),
+            header_size: u32::from_le_bytes(field(bytes, 0x04)),
+            total_bytes: u64::from_le_bytes(field(bytes, 0x08)),
+            block_size: u32::from_le_bytes(field(bytes, 0x10)),
+            ver: bytes[0x14],
+            align: bytes[0x15],
+            rsv_06: field(bytes, 0x16),
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0x00..0x04].copy_from_slice(&self.magic);
+        bytes[0x04..0x08].copy_from_slice(&self.header_size.to_le_bytes());
+        bytes[0x08..0x10].copy_from_slice(&self.total_bytes.to_le_bytes());
+        bytes[0x10..0x14].copy_from_slice(&self.block_size.to_le_bytes());
+        bytes[0x14] = self.ver;
+        bytes[0x15] = self.align;
+        bytes[0x16..0x18].copy_from_slice(&self.rsv_06);
+        bytes
+    }
+
+    /// Number of blocks of the image, the index holding one more entry for the end of the data.
+    #[must_use]
+    pub fn total_blocks(&self) -> u64 {
+        self.total_bytes.div_ceil(u64::from(self.block_size))
+    }
+
+    /// Reads and validates a header.
+    pub fn read_from(r: &mut impl io::Read) -> Result<Self, CisoError> {
+        let mut bytes = [0u8; Self::SIZE];
+        r.read_exact(&mut bytes)?;
+
+        let header = Self::from_bytes(&bytes);
+        header.validate()?;
+
+        Ok(header)
     }
 
     pub fn write_into(&self, w: &mut impl io::Write) -> io::Result<()> {
-        write_struct(w, self)
+        w.write_all(&self.to_bytes())
     }
-}
 
-fn read_struct<T>(r: &mut impl io::Read) -> io::Result<T> {
-    let mut val = mem::MaybeUninit::<T>::uninit();
-    unsafe {
-        let buf = slice::from_raw_parts_mut(val.as_mut_ptr().cast::<u8>(), mem::size_of::<T>());
-        r.read_exact(buf)?;
-        Ok(val.assume_init())
+    /// Checks the fields every reader relies on.
+    pub fn validate(&self) -> Result<(), CisoError> {
+        if self.magic != *b"CISO" && self.magic != *b"ZISO" {
+            return Err(CisoError::BadMagic { magic: self.magic });
+        }
+
+        if self.header_size as usize != Self::SIZE {
+            return Err(CisoError::BadHeaderSize {
+                size: self.header_size,
+            });
+        }
+
+        // Some early tools wrote version 0 for the very same layout as version 1, ZSO has no v2
+        if self.ver > 2 || (self.format() == CisoFormat::Zso && self.ver > 1) {
+            return Err(CisoError::UnsupportedVersion { version: self.ver });
+        }
+
+        // Larger blocks would let a hostile header make readers allocate gigabytes per block
+        if !self.block_size.is_power_of_two() || self.block_size > MAX_BLOCK_SIZE {
+            return Err(CisoError::InvalidBlockSize {
+                block_size: self.block_size,
+            });
+        }
+
+        if self.align > 31 {
+            return Err(CisoError::InvalidAlign { align: self.align });
+        }
+
+        // CSO v2 tells plain blocks by their stored size, which padding alone must not reach
+        if self.format() == CisoFormat::Cso2 && 1u64 << self.align >= u64::from(self.block_size) {
+            return Err(CisoError::InvalidAlign { align: self.align });
+        }
+
+        Ok(())
     }
 }
 
-fn write_struct<T>(w: &mut impl io::Write, val: &T) -> io::Result<()> {
-    unsafe {
-        let buf = slice::from_raw_parts(std::ptr::from_ref(val).cast::<u8>(), mem::size_of::<T>());
-        w.write_all(buf)
-    }
+fn field<const N: usize>(bytes: &[u8; CisoHeader::SIZE], at: usize) -> [u8; N] {
+    std::array::from_fn(|i| bytes[at + i])
 }
@@ -1,15 +1,21 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufWriter, Seek, SeekFrom, Write};
-use std::mem;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
-use flate2::{Compress, Compression, FlushCompress};
+use flate2::{Compress, Compression, FlushCompress, Status};
 use memmap2::Mmap;
-use parking_lot::{Condvar, Mutex};
 
-use crate::ciso_header::CisoHeader;
+use crate::block::decode_stored;
+use crate::cancel::CancellationToken;
+use crate::ciso_header::{CisoFormat, CisoHeader};
+use crate::error::CisoError;
+use crate::index::{BlockCodec, CisoIndex};
+use crate::lz4;
+use crate::progress::{Observer, Progress, ProgressObserver};
+use crate::queue::BoundedQueue;
 
 #[derive(Clone)]
 enum Job {
@@ -17,46 +23,238 @@ enum Job {
     End,
 }
 
-#[derive(Clone)]
 struct Block {
     index: usize,
+    codec: BlockCodec,
     compressed: Vec<u8>, // empty = plain
 }
 
+/// Block contents making the encoder fail or panic in tests, to exercise the worker error paths.
+#[cfg(test)]
+const FAULT_ERROR: &[u8] = b"\0ciso-fault:error";
+#[cfg(test)]
+const FAULT_PANIC: &[u8] = b"\0ciso-fault:panic";
+
+pub const MIN_BLOCK_SIZE: u32 = 0x800;
+pub const MAX_BLOCK_SIZE: u32 = 0x10_0000;
+
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub(crate) level: Option<u32>, // Defaults to 6, recompression copies blocks only when unset
+    pub(crate) block_size: u32,
+    align: Option<u8>, // Defaults to the smallest shift fitting the image
+    format: CisoFormat,
+    threads: Option<usize>,     // Defaults to the number of CPUs
+    queue_depth: Option<usize>, // Defaults to twice the number of threads
+    pub(crate) progress: Observer,
+    pub(crate) cancel: CancellationToken,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            level: None,
+            block_size: 0x800,
+            align: None,
+            format: CisoFormat::Cso,
+            threads: None,
+            queue_depth: None,
+            progress: Observer::default(),
+            cancel: CancellationToken::default(),
+        }
+    }
+}
+
+impl CompressOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compression level, from 0 (store) to 9 (best).
+    ///
+    /// ZSO uses it as the LZ4 HC level, up to 12, 0 selecting the fast LZ4 compressor.
+    #[must_use]
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Decompressed size of a block, a power of two between 2 KiB and 1 MiB.
+    #[must_use]
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Index alignment shift, every block starts on a `1 << align` boundary.
+    ///
+    /// When unset, the smallest shift able to address the whole output is picked.
+    #[must_use]
+    pub fn align(mut self, align: u8) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Layout of the output, CSO v2 picking the smallest of deflate and LZ4 for each block, ZSO
+    /// using LZ4 only.
+    #[must_use]
+    pub fn format(mut self, format: CisoFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Number of block compression threads.
+    #[must_use]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Number of blocks buffered between the reader, the workers and the writer.
+    #[must_use]
+    pub fn queue_depth(mut self, queue_depth: usize) -> Self {
+        self.queue_depth = Some(queue_depth);
+        self
+    }
+
+    /// Observer notified each time a block is written to the output.
+    #[must_use]
+    pub fn progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Observer::new(observer);
+        self
+    }
+
+    /// Token making the compression stop with `CisoError::Cancelled` once cancelled.
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Upper bound of the output size for an input of `total_bytes`, reached when every block is stored plain.
+    #[must_use]
+    pub fn max_output_size(&self, total_bytes: u64) -> u64 {
+        let align = self
+            .align
+            .unwrap_or_else(|| CisoHeader::min_align(total_bytes, self.block_size));
+        let total_blocks = total_bytes.div_ceil(u64::from(self.block_size));
+        let index_size = (total_blocks + 1) * 4;
+        let max_padding = (total_blocks + 1) * ((1 << align) - 1);
+
+        CisoHeader::SIZE as u64 + index_size + max_padding + total_bytes
+    }
+
+    pub(crate) fn effective_level(&self) -> u32 {
+        self.level.unwrap_or(6)
+    }
+
+    pub fn validate(&self) -> Result<(), CisoError> {
+        let max_level = match self.format {
+            CisoFormat::Cso | CisoFormat::Cso2 => 9,
+            CisoFormat::Zso => 12,
+        };
+        let level = self.effective_level();
+        if level > max_level {
+            return Err(CisoError::InvalidOption {
+                name: "level",
+                value: u64::from(level),
+            });
+        }
+
+        if !self.block_size.is_power_of_two()
+            || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.block_size)
+        {
+            return Err(CisoError::InvalidOption {
+                name: "block_size",
+                value: u64::from(self.block_size),
+            });
+        }
+
+        if let Some(align @ 32..) = self.align {
+            return Err(CisoError::InvalidOption {
+                name: "align",
+                value: u64::from(align),
+            });
+        }
+
+        // CSO v2 padding must stay below a block, see `CisoHeader::validate`
+        if let Some(align) = self.align
+            && self.format == CisoFormat::Cso2
+            && 1u64 << align >= u64::from(self.block_size)
+        {
+            return Err(CisoError::InvalidOption {
+                name: "align",
+                value: u64::from(align),
+            });
+        }
+
+        if let Some(n @ 0) = self.threads {
+            return Err(CisoError::InvalidOption {
+                name: "threads",
+                value: n as u64,
+            });
+        }
+
+        if let Some(n @ 0) = self.queue_depth {
+            return Err(CisoError::InvalidOption {
+                name: "queue_depth",
+                value: n as u64,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub fn compress_ciso(input: File, output: File, level: u32) -> Result<(), CisoError> {
+    compress_ciso_with(input, output, &CompressOptions::new().level(level))
+}
+
 #[expect(clippy::cast_possible_truncation)]
-pub fn compress_ciso(mut input: File, mut output: File, level: u32) -> io::Result<()> {
-    let num_cpus = num_cpus::get();
-    let queue_cap = num_cpus * 2;
-    let threads = num_cpus.max(1); // Nb of threads used for block compression
+pub fn compress_ciso_with(
+    mut input: File,
+    mut output: File,
+    options: &CompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
+
+    // Nb of threads used for block compression
+    let threads = options.threads.unwrap_or_else(num_cpus::get).max(1);
+    let queue_cap = options.queue_depth.unwrap_or(threads * 2);
+    let level = options.effective_level();
 
     let total_bytes = input.seek(SeekFrom::End(0))?;
     input.seek(SeekFrom::Start(0))?;
 
-    let header = CisoHeader::new(total_bytes);
+    let mut index = output_index(total_bytes, options)?;
+    let header = *index.header();
     let block_size = header.block_size as usize;
     let total_blocks = (total_bytes as usize).div_ceil(block_size);
 
-    let index_size = (total_blocks + 1) * 4;
-    let mut index = vec![0u32; total_blocks + 1];
-
-    // Write header and empty bytes into output
-    header.write_into(&mut output)?;
-    output.write_all(&vec![0u8; index_size])?;
+    let write_pos = write_placeholder(&mut output, &index)?;
 
     let mmap = Arc::new(unsafe { Mmap::map(&input)? });
 
     let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
-    let mut write_pos = mem::size_of::<CisoHeader>() as u64 + index_size as u64;
 
     let jobs = BoundedQueue::<Job>::new(queue_cap);
-    let results = BoundedQueue::<Block>::new(queue_cap);
+    let results = BoundedQueue::<Result<Block, CisoError>>::new(queue_cap);
 
     let mut handles: Vec<JoinHandle<()>> = Vec::new();
 
     {
         let jobs = jobs.clone();
+        let results = results.clone();
+        let cancel = options.cancel.clone();
         handles.push(thread::spawn(move || {
             for index in 0..total_blocks {
+                if cancel.is_cancelled() {
+                    // Unblock the workers and the writer, which will report the cancellation
+                    jobs.close();
+                    results.close();
+                    return;
+                }
                 jobs.push(Job::Block { index });
             }
             // Send end signal to all threads
@@ -72,124 +270,528 @@ pub fn compress_ciso(mut input: File, mut output: File, level: u32) -> io::Resul
         let mmap = mmap.clone();
 
         handles.push(thread::spawn(move || {
-            compression_task(&jobs, &results, &mmap, block_size, level);
+            compression_task(&jobs, &results, &mmap, &header, level);
         }));
     }
 
-    let mut next = 0usize;
-    let mut pending = HashMap::with_capacity(queue_cap * threads * 2);
+    let mut written = write_blocks(&results, &mut writer, &mut index, &mmap, write_pos, options);
 
-    while next < total_blocks {
-        let block = results.pop();
-        pending.insert(block.index, block);
+    // Whatever happened, make sure no thread is left waiting on a queue
+    jobs.close();
+    results.close();
+    for handle in handles {
+        // Workers catch their own panics, this only guards against bugs in the pipeline
+        if handle.join().is_err() && written.is_ok() {
+            written = Err(CisoError::WorkerPanicked { block: None });
+        }
+    }
 
-        while let Some(block) = pending.remove(&next) {
-            if (write_pos >> header.align) > u64::from(u32::MAX) {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "CSO too large"));
-            }
+    finish_output(&mut writer, index, written?)
+}
 
-            index[next] = (write_pos >> header.align) as u32;
+/// Compresses a stream into a seekable output, both used from their start.
+///
+/// Unlike `compress_ciso_with`, which maps files and compresses on a worker pool, blocks are
+/// read and compressed in order on the calling thread: `threads` and `queue_depth` are ignored.
+#[expect(clippy::cast_possible_truncation)]
+pub fn compress_ciso_stream(
+    mut input: impl Read + Seek,
+    output: impl Write + Seek,
+    options: &CompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
 
-            if block.compressed.is_empty() {
-                index[next] |= 0x8000_0000;
-                let start = next * block_size;
-                let end = (start + block_size).min(mmap.len());
-                writer.write_all(&mmap[start..end])?;
-                write_pos += (end - start) as u64;
-            } else {
-                writer.write_all(&block.compressed)?;
-                write_pos += block.compressed.len() as u64;
-            }
+    let total_bytes = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
 
-            next += 1;
+    let mut index = output_index(total_bytes, options)?;
+    let block_size = index.header().block_size as usize;
+    let total_blocks = (total_bytes as usize).div_ceil(block_size);
+
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    let mut write_pos = write_placeholder(&mut writer, &index)?;
+
+    let mut encoder = BlockEncoder::new(index.header(), options.effective_level());
+    let mut in_buf = vec![0u8; block_size];
+
+    for i in 0..total_blocks {
+        options.cancel.check()?;
+
+        let end = total_bytes.min(((i + 1) * block_size) as u64);
+        let data = &mut in_buf[..end as usize - i * block_size];
+        input.read_exact(data)?;
+
+        let (codec, compressed) = encoder.encode(data, i)?;
+        write_pos = store_block(&mut writer, &mut index, write_pos, data, codec, &compressed)?;
+
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in: end,
+            bytes_out: write_pos,
+        });
+    }
+
+    finish_output(&mut writer, index, write_pos)
+}
+
+/// Compresses a stream of unknown size into an output which cannot be seeked, such as pipes.
+///
+/// Blocks are compressed into `spool` as they arrive, then the header, the index and the
+/// spooled blocks are written to `output` once the input is exhausted. Like
+/// `compress_ciso_stream`, everything runs on the calling thread. Progress reports have a
+/// `total_blocks` of 0 until the input size is known.
+#[expect(clippy::cast_possible_truncation)]
+pub fn compress_ciso_spooled(
+    mut input: impl Read,
+    output: impl Write,
+    spool: impl Read + Write + Seek,
+    options: &CompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
+
+    let block_size = options.block_size as usize;
+
+    // The alignment depends on the final size, blocks it pads up to a full one are stored plain later
+    let provisional = CisoHeader::with_format(
+        0,
+        options.block_size,
+        options.align.unwrap_or(0),
+        options.format,
+    );
+    let mut encoder = BlockEncoder::new(&provisional, options.effective_level());
+
+    let mut spool = BufWriter::with_capacity(1 << 20, spool); // 1MiB
+    let mut in_buf = Vec::with_capacity(block_size);
+
+    let mut spooled_blocks = Vec::new(); // Codec and size of each spooled block
+    let mut total_bytes = 0;
+    let mut spooled = 0;
+
+    loop {
+        options.cancel.check()?;
+
+        in_buf.clear();
+        (&mut input)
+            .take(block_size as u64)
+            .read_to_end(&mut in_buf)?;
+        if in_buf.is_empty() {
+            break;
         }
+
+        let i = spooled_blocks.len();
+        let (codec, compressed) = encoder.encode(&in_buf, i)?;
+        let stored = if codec == BlockCodec::Plain {
+            &in_buf
+        } else {
+            &compressed
+        };
+        spool.write_all(stored)?;
+
+        // At most twice the block size
+        spooled_blocks.push((codec, stored.len() as u32));
+        total_bytes += in_buf.len() as u64;
+        spooled += stored.len() as u64;
+
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks: 0,
+            bytes_in: total_bytes,
+            bytes_out: spooled,
+        });
+
+        if in_buf.len() < block_size {
+            break;
+        }
+    }
+
+    // Now that the blocks are known, lay them out to fill the index before any of them is written
+    let mut index = output_index(total_bytes, options)?;
+    let align = index.header().align;
+    let mut write_pos = index.data_start();
+    for (i, &(codec, len)) in spooled_blocks.iter().enumerate() {
+        write_pos = write_pos.next_multiple_of(1 << align);
+        let codec = final_codec(&index, i, codec, len);
+        index.push_codec(write_pos, codec)?;
+        write_pos += stored_len(&index, i, codec, len);
     }
+    write_pos = write_pos.next_multiple_of(1 << align);
+    index.push(write_pos, false)?;
 
-    index[total_blocks] = (write_pos >> header.align) as u32;
+    let mut spool = spool.into_inner().map_err(io::IntoInnerError::into_error)?;
+    spool.seek(SeekFrom::Start(0))?;
+    let mut spool = BufReader::with_capacity(1 << 20, spool); // 1MiB
 
-    writer.seek(SeekFrom::Start(mem::size_of::<CisoHeader>() as u64))?;
-    for i in index {
-        writer.write_all(&i.to_le_bytes())?;
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    index.header().write_into(&mut writer)?;
+    index.write_into(&mut writer)?;
+
+    let mut stored = Vec::with_capacity(block_size * 2);
+    let mut plain = Vec::with_capacity(block_size);
+    let mut pos = index.data_start();
+    for (i, &(codec, len)) in spooled_blocks.iter().enumerate() {
+        options.cancel.check()?;
+
+        stored.resize(len as usize, 0);
+        spool.read_exact(&mut stored)?;
+
+        let data = if codec != BlockCodec::Plain && index.codec(i) == BlockCodec::Plain {
+            let range = index.block_range(i);
+            plain.resize((range.end - range.start) as usize, 0);
+            decode_stored(codec, &stored, &mut plain, i, pos)?;
+            &plain
+        } else {
+            &stored
+        };
+
+        pos += pad_to_align(&mut writer, pos, align)?;
+        writer.write_all(data)?;
+        pos += data.len() as u64;
     }
+    pad_to_align(&mut writer, pos, align)?;
 
     writer.flush()?;
 
-    for handle in handles {
-        let _ = handle.join();
+    options.progress.report(&Progress {
+        blocks_done: spooled_blocks.len(),
+        total_blocks: spooled_blocks.len(),
+        bytes_in: total_bytes,
+        bytes_out: write_pos,
+    });
+
+    Ok(())
+}
+
+/// How spooled block `i` is stored in the output, CSO v2 storing plain the blocks padded up
+/// to their decompressed size.
+fn final_codec(index: &CisoIndex, i: usize, codec: BlockCodec, len: u32) -> BlockCodec {
+    let range = index.block_range(i);
+    let padded = u64::from(len).next_multiple_of(1 << index.header().align);
+
+    if index.header().format() == CisoFormat::Cso2 && padded >= range.end - range.start {
+        BlockCodec::Plain
+    } else {
+        codec
+    }
+}
+
+/// Stored size of block `i`, `len` bytes once compressed with `codec`.
+fn stored_len(index: &CisoIndex, i: usize, codec: BlockCodec, len: u32) -> u64 {
+    if codec == BlockCodec::Plain {
+        let range = index.block_range(i);
+        range.end - range.start
+    } else {
+        u64::from(len)
     }
+}
+
+/// Empty index of the output, its header laid out for an input of `total_bytes`.
+pub(crate) fn output_index(
+    total_bytes: u64,
+    options: &CompressOptions,
+) -> Result<CisoIndex, CisoError> {
+    let align = options
+        .align
+        .unwrap_or_else(|| CisoHeader::min_align(total_bytes, options.block_size));
+    let header = CisoHeader::with_format(total_bytes, options.block_size, align, options.format);
+    // Images too large for CSO v2 need an alignment it doesn't allow
+    header.validate()?;
+
+    Ok(CisoIndex::new(header))
+}
+
+/// Writes the header followed by a zeroed index, filled by `finish_output`.
+///
+/// Returns the position of the first block.
+pub(crate) fn write_placeholder(w: &mut impl Write, index: &CisoIndex) -> io::Result<u64> {
+    index.header().write_into(w)?;
+
+    let data_start = index.data_start();
+    io::copy(
+        &mut io::repeat(0).take(data_start - CisoHeader::SIZE as u64),
+        w,
+    )?;
+
+    Ok(data_start)
+}
+
+/// Appends a block, its `plain` data or the `compressed` one, and pushes its index entry.
+///
+/// Returns the output position after the block.
+pub(crate) fn store_block(
+    writer: &mut impl Write,
+    index: &mut CisoIndex,
+    mut write_pos: u64,
+    plain: &[u8],
+    codec: BlockCodec,
+    compressed: &[u8],
+) -> Result<u64, CisoError> {
+    write_pos += pad_to_align(writer, write_pos, index.header().align)?;
+    index.push_codec(write_pos, codec)?;
+
+    let stored = if codec == BlockCodec::Plain {
+        plain
+    } else {
+        compressed
+    };
+    writer.write_all(stored)?;
+
+    Ok(write_pos + stored.len() as u64)
+}
+
+/// Pads the end of the data and stores the complete index in place of the placeholder.
+pub(crate) fn finish_output(
+    writer: &mut (impl Write + Seek),
+    mut index: CisoIndex,
+    mut write_pos: u64,
+) -> Result<(), CisoError> {
+    write_pos += pad_to_align(writer, write_pos, index.header().align)?;
+    index.push(write_pos, false)?;
+
+    writer.seek(SeekFrom::Start(CisoHeader::SIZE as u64))?;
+    index.write_into(writer)?;
+
+    writer.flush()?;
 
     Ok(())
 }
 
-#[expect(clippy::cast_possible_truncation)]
+/// Ordered writer loop, stores the blocks and fills their index entries.
+///
+/// Returns the output position after the last block.
+fn write_blocks(
+    results: &BoundedQueue<Result<Block, CisoError>>,
+    writer: &mut impl Write,
+    index: &mut CisoIndex,
+    mmap: &Mmap,
+    mut write_pos: u64,
+    options: &CompressOptions,
+) -> Result<u64, CisoError> {
+    let block_size = index.header().block_size as usize;
+    let total_blocks = mmap.len().div_ceil(block_size);
+
+    let mut next = 0usize;
+    let mut pending = HashMap::with_capacity(results.capacity * 2);
+
+    while next < total_blocks {
+        options.cancel.check()?;
+
+        // Queues are only closed early on cancellation
+        let Some(block) = results.pop() else {
+            return Err(CisoError::Cancelled);
+        };
+        let block = block?;
+        pending.insert(block.index, block);
+
+        while let Some(block) = pending.remove(&next) {
+            let start = next * block_size;
+            let end = (start + block_size).min(mmap.len());
+            write_pos = store_block(
+                writer,
+                index,
+                write_pos,
+                &mmap[start..end],
+                block.codec,
+                &block.compressed,
+            )?;
+
+            next += 1;
+
+            options.progress.report(&Progress {
+                blocks_done: next,
+                total_blocks,
+                bytes_in: end as u64,
+                bytes_out: write_pos,
+            });
+        }
+    }
+
+    Ok(write_pos)
+}
+
+/// Writes zeros up to the next `1 << align` boundary, returning the padding size.
+pub(crate) fn pad_to_align(w: &mut impl Write, pos: u64, align: u8) -> io::Result<u64> {
+    let pad = pos.next_multiple_of(1 << align) - pos;
+    io::copy(&mut io::repeat(0).take(pad), w)
+}
+
 fn compression_task(
     jobs: &BoundedQueue<Job>,
-    results: &BoundedQueue<Block>,
+    results: &BoundedQueue<Result<Block, CisoError>>,
     mmap: &Mmap,
-    block_size: usize,
+    header: &CisoHeader,
     level: u32,
 ) {
-    let mut out_buf = vec![0u8; block_size * 2];
-    let mut comp = Compress::new(Compression::new(level), false);
+    let block_size = header.block_size as usize;
+    let mut encoder = BlockEncoder::new(header, level);
+
+    while let Some(Job::Block { index }) = jobs.pop() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let start = index * block_size;
+            let end = (start + block_size).min(mmap.len());
+            encoder.encode(&mmap[start..end], index)
+        }))
+        .unwrap_or(Err(CisoError::WorkerPanicked { block: Some(index) }));
+
+        // The writer gives up on the first error, no need to go on
+        let failed = result.is_err();
+        let result = result.map(|(codec, compressed)| Block {
+            index,
+            codec,
+            compressed,
+        });
+        if !results.push(result) || failed {
+            break;
+        }
+    }
+}
 
-    loop {
-        match jobs.pop() {
-            Job::End => break,
-            Job::Block { index } => {
-                comp.reset();
-
-                let start = index * block_size;
-                let end = (start + block_size).min(mmap.len());
-                let input = &mmap[start..end];
-
-                comp.compress(input, &mut out_buf, FlushCompress::Finish)
-                    .unwrap();
-                let size = comp.total_out() as usize;
-
-                let mut compressed = Vec::new();
-                if size < input.len() {
-                    compressed.reserve(size);
-                    compressed.extend_from_slice(&out_buf[..size]);
-                }
+/// Deflates `input`, returns an empty buffer if the block is better stored plain.
+#[expect(clippy::cast_possible_truncation)]
+fn compress_block(
+    comp: &mut Compress,
+    input: &[u8],
+    out_buf: &mut [u8],
+    index: usize,
+) -> Result<Vec<u8>, CisoError> {
+    comp.reset();
+
+    let status = comp
+        .compress(input, out_buf, FlushCompress::Finish)
+        .map_err(|_| CisoError::CompressFailed { block: index })?;
+    // Anything but the end of the stream means the output buffer was too small
+    if status != Status::StreamEnd {
+        return Err(CisoError::CompressFailed { block: index });
+    }
 
-                results.push(Block { index, compressed });
-            }
-        }
+    let size = comp.total_out() as usize;
+    if size >= input.len() {
+        return Ok(Vec::new());
     }
+
+    Ok(out_buf[..size].to_vec())
 }
 
-#[derive(Clone)]
-struct BoundedQueue<T> {
-    capacity: usize,
-    inner: Arc<(Mutex<VecDeque<T>>, Condvar)>,
+/// Compresses the blocks of an image with the codecs its format allows.
+pub(crate) struct BlockEncoder {
+    format: CisoFormat,
+    level: u32,
+    align: u8,
+    comp: Compress,
+    out_buf: Vec<u8>,
+    lz4_buf: Vec<u8>,
 }
 
-impl<T> BoundedQueue<T> {
-    fn new(capacity: usize) -> Self {
+impl BlockEncoder {
+    pub(crate) fn new(header: &CisoHeader, level: u32) -> Self {
+        let block_size = header.block_size as usize;
+        let format = header.format();
+
         Self {
-            capacity,
-            inner: Arc::new((Mutex::new(VecDeque::new()), Condvar::new())),
+            format,
+            level,
+            align: header.align,
+            // ZSO levels go up to the LZ4 HC ones, deflate is never used there
+            comp: Compress::new(Compression::new(level.min(9)), false),
+            out_buf: vec![0u8; block_size * 2],
+            lz4_buf: match format {
+                CisoFormat::Cso => Vec::new(),
+                CisoFormat::Cso2 | CisoFormat::Zso => {
+                    vec![0u8; lz4::max_compressed_size(block_size)]
+                }
+            },
         }
     }
 
-    fn push(&self, val: T) {
-        let (lock, cvar) = &*self.inner;
-        let mut queue = lock.lock();
-        while queue.len() >= self.capacity {
-            cvar.wait(&mut queue);
+    /// Compresses block `index`, returning an empty buffer when it is stored plain.
+    ///
+    /// CSO v2 keeps the smallest of deflate and LZ4, and stores plain the blocks whose padded
+    /// size reaches their decompressed one, as readers tell them apart by size.
+    pub(crate) fn encode(
+        &mut self,
+        input: &[u8],
+        index: usize,
+    ) -> Result<(BlockCodec, Vec<u8>), CisoError> {
+        #[cfg(test)]
+        if input.starts_with(FAULT_ERROR) {
+            return Err(CisoError::CompressFailed { block: index });
+        } else if input.starts_with(FAULT_PANIC) {
+            panic!("injected panic");
         }
-        queue.push_back(val);
-        cvar.notify_one();
-    }
 
-    fn pop(&self) -> T {
-        let (lock, cvar) = &*self.inner;
-        let mut queue = lock.lock();
-        while queue.is_empty() {
-            cvar.wait(&mut queue);
+        if self.format == CisoFormat::Zso {
+            let lz4_len = self.lz4(input, index)?;
+            if lz4_len >= input.len() {
+                return Ok((BlockCodec::Plain, Vec::new()));
+            }
+            return Ok((BlockCodec::Lz4, self.lz4_buf[..lz4_len].to_vec()));
         }
-        let value = queue.pop_front().unwrap(); // Safety: emptyness checked above
-        cvar.notify_one();
-        value
+
+        let deflated = compress_block(&mut self.comp, input, &mut self.out_buf, index)?;
+        if self.format == CisoFormat::Cso {
+            let codec = if deflated.is_empty() {
+                BlockCodec::Plain
+            } else {
+                BlockCodec::Deflate
+            };
+            return Ok((codec, deflated));
+        }
+
+        let lz4_len = self.lz4(input, index)?;
+
+        let (codec, compressed) = if !deflated.is_empty() && deflated.len() < lz4_len {
+            (BlockCodec::Deflate, deflated)
+        } else {
+            (BlockCodec::Lz4, self.lz4_buf[..lz4_len].to_vec())
+        };
+
+        let padded = compressed.len().next_multiple_of(1 << self.align);
+        if padded >= input.len() {
+            return Ok((BlockCodec::Plain, Vec::new()));
+        }
+
+        Ok((codec, compressed))
+    }
+
+    /// Compresses `input` into the LZ4 buffer, returning its size.
+    fn lz4(&mut self, input: &[u8], index: usize) -> Result<usize, CisoError> {
+        lz4::compress(input, &mut self.lz4_buf, self.level)
+            .map_err(|_| CisoError::CompressFailed { block: index })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Compresses an image whose block `block` starts with the `fault` contents.
+    fn compress_with_fault(fault: &[u8], block: usize) -> Result<(), CisoError> {
+        let tmp = tempfile::tempdir()?;
+        let iso_path = tmp.path().join("input.iso");
+        let cso_path = tmp.path().join("output.cso");
+
+        let mut data: Vec<u8> = (0..=255u8).cycle().take(64 * 0x800).collect();
+        data[block * 0x800..][..fault.len()].copy_from_slice(fault);
+        fs::write(&iso_path, data)?;
+
+        let options = CompressOptions::new().threads(3).queue_depth(2);
+        compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)
+    }
+
+    #[test]
+    fn worker_error_is_returned() {
+        let err = compress_with_fault(FAULT_ERROR, 17);
+        assert!(matches!(err, Err(CisoError::CompressFailed { block: 17 })));
+    }
+
+    #[test]
+    fn worker_panic_is_returned() {
+        let err = compress_with_fault(FAULT_PANIC, 40);
+        assert!(matches!(
+            err,
+            Err(CisoError::WorkerPanicked { block: Some(40) })
+        ));
     }
 }
@@ -1,54 +1,305 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
-use flate2::{Decompress, FlushDecompress, Status};
+use memmap2::Mmap;
 
+use crate::block::decode_block;
+use crate::cancel::CancellationToken;
 use crate::ciso_header::CisoHeader;
+use crate::error::CisoError;
+use crate::index::CisoIndex;
+use crate::progress::{Observer, Progress, ProgressObserver};
+use crate::queue::{BoundedQueue, PARALLEL_MIN_BLOCKS};
 
-#[expect(clippy::cast_possible_truncation)]
-pub fn decompress_ciso(mut input: File, mut output: File) -> io::Result<()> {
-    let header = CisoHeader::read_from(&mut input)?;
-    let block_size = header.block_size as usize;
-    let total_blocks = (header.total_bytes as usize) / block_size;
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    threads: Option<usize>, // Defaults to the number of CPUs
+    pub(crate) progress: Observer,
+    pub(crate) cancel: CancellationToken,
+}
+
+impl DecompressOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of block decompression threads, 1 inflates every block on the calling thread.
+    #[must_use]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Observer notified each time a block is written to the output.
+    #[must_use]
+    pub fn progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Observer::new(observer);
+        self
+    }
+
+    /// Token making the decompression stop with `CisoError::Cancelled` once cancelled.
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), CisoError> {
+        if let Some(n @ 0) = self.threads {
+            return Err(CisoError::InvalidOption {
+                name: "threads",
+                value: n as u64,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub fn decompress_ciso(input: File, output: impl Write) -> Result<(), CisoError> {
+    decompress_ciso_with(input, output, &DecompressOptions::new())
+}
+
+/// Decompresses a CSO file into any output, such as another file or a pipe.
+pub fn decompress_ciso_with(
+    mut input: File,
+    output: impl Write,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
 
-    let mut index = vec![0u32; total_blocks + 1];
-    for v in &mut index {
-        let mut buf = [0u8; 4];
-        input.read_exact(&mut buf)?;
-        *v = u32::from_le_bytes(buf);
+    let file_len = input.metadata()?.len();
+    let index = read_layout(&mut input, file_len)?;
+    let total_blocks = index.len();
+
+    let threads = options.threads.unwrap_or_else(num_cpus::get).max(1);
+
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    if threads == 1 || total_blocks < PARALLEL_MIN_BLOCKS {
+        decompress_sequential(&mut input, &mut writer, &index, options)?;
+    } else {
+        decompress_parallel(&input, &mut writer, index, threads, options)?;
     }
 
-    let mut in_buf = vec![0u8; block_size * 2];
-    let mut out_buf = vec![0u8; block_size];
+    Ok(writer.flush()?)
+}
+
+/// Decompresses a CSO stream, read from its start, into any output.
+///
+/// Unlike `decompress_ciso_with`, which maps files and inflates them on a worker pool, blocks
+/// are inflated in order on the calling thread: `threads` is ignored.
+pub fn decompress_ciso_stream(
+    mut input: impl Read + Seek,
+    output: impl Write,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
+
+    let file_len = input.seek(SeekFrom::End(0))?;
+    let index = read_layout(&mut input, file_len)?;
+
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    decompress_sequential(&mut input, &mut writer, &index, options)?;
+
+    Ok(writer.flush()?)
+}
+
+/// Reads the header and index at the start of a CSO file of `file_len` bytes.
+fn read_layout(input: &mut (impl Read + Seek), file_len: u64) -> Result<CisoIndex, CisoError> {
+    input.seek(SeekFrom::Start(0))?;
+    let header = CisoHeader::read_from(input)?;
+
+    CisoIndex::read_from(input, &header, file_len)
+}
+
+/// Inflates the blocks in order on the calling thread.
+fn decompress_sequential(
+    input: &mut (impl Read + Seek),
+    writer: &mut impl Write,
+    index: &CisoIndex,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    let total_blocks = index.len();
+
+    let mut in_buf = Vec::new();
+    let mut out_buf = Vec::with_capacity(index.header().block_size as usize);
+    let mut bytes_in = 0;
+    let mut bytes_out = 0;
 
     for i in 0..total_blocks {
-        let raw = index[i];
-        let plain = raw & 0x8000_0000 != 0;
+        options.cancel.check()?;
 
-        let off = u64::from(raw & 0x7fff_ffff) << header.align;
-        input.seek(SeekFrom::Start(off))?;
+        // The final block is shorter when the image isn't a multiple of the block size
+        decode_block(index, i, &mut in_buf, &mut out_buf, |off, buf| {
+            input.seek(SeekFrom::Start(off))?;
+            input.read_exact(buf)
+        })?;
 
-        if plain {
-            input.read_exact(&mut out_buf)?;
-        } else {
-            let next = u64::from(index[i + 1] & 0x7fff_ffff) << header.align;
-            let size = (next - off) as usize;
+        writer.write_all(&out_buf)?;
+        bytes_in += stored_size(index, i, out_buf.len() as u64);
+        bytes_out += out_buf.len() as u64;
 
-            input.read_exact(&mut in_buf[..size])?;
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in,
+            bytes_out,
+        });
+    }
+
+    Ok(())
+}
 
-            let status = Decompress::new(false).decompress(
-                &in_buf[..size],
-                &mut out_buf,
-                FlushDecompress::Finish,
-            )?;
+/// Inflates the blocks on a pool of workers reading the mapped input, written back in order.
+fn decompress_parallel(
+    input: &File,
+    writer: &mut impl Write,
+    index: CisoIndex,
+    threads: usize,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    let mmap = Arc::new(unsafe { Mmap::map(input)? });
+    let index = Arc::new(index);
+    let total_blocks = index.len();
 
-            if status != Status::StreamEnd {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "zlib error"));
+    // `None` tells a worker to stop
+    let jobs = BoundedQueue::<Option<usize>>::new(threads * 2);
+    let results = BoundedQueue::<(usize, Result<Vec<u8>, CisoError>)>::new(threads * 2);
+
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    {
+        let jobs = jobs.clone();
+        let results = results.clone();
+        let cancel = options.cancel.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..total_blocks {
+                if cancel.is_cancelled() {
+                    // Unblock the workers and the writer, which will report the cancellation
+                    jobs.close();
+                    results.close();
+                    return;
+                }
+                jobs.push(Some(i));
             }
-        }
+            for _ in 0..threads {
+                jobs.push(None);
+            }
+        }));
+    }
+
+    for _ in 0..threads {
+        let jobs = jobs.clone();
+        let results = results.clone();
+        let mmap = mmap.clone();
+        let index = index.clone();
+
+        handles.push(thread::spawn(move || {
+            decompression_task(&jobs, &results, &mmap, &index);
+        }));
+    }
+
+    let written = write_blocks(&results, writer, &index, options);
 
-        output.write_all(&out_buf)?;
+    // Whatever happened, make sure no thread is left waiting on a queue
+    jobs.close();
+    results.close();
+    let mut panicked = false;
+    for handle in handles {
+        panicked |= handle.join().is_err();
+    }
+
+    written?;
+    if panicked {
+        return Err(CisoError::WorkerPanicked { block: None });
     }
 
     Ok(())
 }
+
+/// Ordered writer loop, reports the error of the first failing block.
+fn write_blocks(
+    results: &BoundedQueue<(usize, Result<Vec<u8>, CisoError>)>,
+    writer: &mut impl Write,
+    index: &CisoIndex,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    let total_blocks = index.len();
+
+    let mut next = 0usize;
+    let mut pending = HashMap::with_capacity(results.capacity * 2);
+    let mut bytes_in = 0;
+    let mut bytes_out = 0;
+
+    while next < total_blocks {
+        options.cancel.check()?;
+
+        // Queues are only closed early on cancellation
+        let Some((i, block)) = results.pop() else {
+            return Err(CisoError::Cancelled);
+        };
+        pending.insert(i, block);
+
+        while let Some(block) = pending.remove(&next) {
+            let block = block?;
+
+            writer.write_all(&block)?;
+            bytes_in += stored_size(index, next, block.len() as u64);
+            bytes_out += block.len() as u64;
+
+            next += 1;
+
+            options.progress.report(&Progress {
+                blocks_done: next,
+                total_blocks,
+                bytes_in,
+                bytes_out,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn decompression_task(
+    jobs: &BoundedQueue<Option<usize>>,
+    results: &BoundedQueue<(usize, Result<Vec<u8>, CisoError>)>,
+    mmap: &Mmap,
+    index: &CisoIndex,
+) {
+    let mut in_buf = Vec::new();
+
+    while let Some(Some(i)) = jobs.pop() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut out = Vec::with_capacity(index.header().block_size as usize);
+            decode_block(index, i, &mut in_buf, &mut out, |off, buf| {
+                let data = usize::try_from(off)
+                    .ok()
+                    .and_then(|off| mmap.get(off..off + buf.len()))
+                    .ok_or(io::ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(data);
+                Ok(())
+            })?;
+            Ok(out)
+        }))
+        .unwrap_or(Err(CisoError::WorkerPanicked { block: Some(i) }));
+
+        // Keep going after a failure, the writer needs every block before the failing one
+        if !results.push((i, result)) {
+            break;
+        }
+    }
+}
+
+/// Size of block `i` in the CSO file, `plain_size` for plain blocks.
+fn stored_size(index: &CisoIndex, i: usize, plain_size: u64) -> u64 {
+    match index.entry(i) {
+        Some(entry) if !entry.is_plain => entry.stored_len,
+        _ => plain_size,
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::ciso_header::{CisoFormat, CisoHeader};
+use crate::error::CisoError;
+use crate::legacy::LegacyFormat;
+
+/// Offset of the first ISO9660 volume descriptor, in sector 16, and its `CD001` identifier.
+const ISO9660_DESCRIPTOR: u64 = 0x8000;
+const ISO9660_ID: &[u8; 5] = b"CD001";
+
+/// Kind of image, as told by its content rather than its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Uncompressed ISO9660 image.
+    Iso,
+    /// CSO v1, v2 or ZSO image.
+    Ciso(CisoFormat),
+    /// DAX or JSO image.
+    Legacy(LegacyFormat),
+}
+
+/// Tells the format of an image from its magic bytes, or from the ISO9660 volume descriptor.
+///
+/// Returns `None` when no signature matches, e.g. for raw images without a filesystem. Only
+/// the magic is looked at: the header is not validated, and the reader is left at its start.
+pub fn detect_format(mut reader: impl Read + Seek) -> Result<Option<ImageFormat>, CisoError> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut bytes = [0u8; CisoHeader::SIZE];
+    let len = read_up_to(&mut reader, &mut bytes)?;
+
+    let format = match &bytes[..4] {
+        _ if len < 4 => None,
+        b"CISO" | b"ZISO" => Some(ImageFormat::Ciso(if len == CisoHeader::SIZE {
+            CisoHeader::from_bytes(&bytes).format()
+        } else if &bytes[..4] == b"ZISO" {
+            CisoFormat::Zso
+        } else {
+            CisoFormat::Cso
+        })),
+        b"DAX\0" => Some(ImageFormat::Legacy(LegacyFormat::Dax)),
+        b"JISO" => Some(ImageFormat::Legacy(LegacyFormat::Jso)),
+        _ => {
+            // The descriptor type comes first, its identifier right after
+            let mut descriptor = [0u8; 6];
+            reader.seek(SeekFrom::Start(ISO9660_DESCRIPTOR))?;
+            let len = read_up_to(&mut reader, &mut descriptor)?;
+            (len == descriptor.len() && &descriptor[1..] == ISO9660_ID).then_some(ImageFormat::Iso)
+        }
+    };
+
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(format)
+}
+
+/// Fills `buf` as far as the input goes, returning how many bytes were read.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, CisoError> {
+    let mut bytes = Vec::with_capacity(buf.len());
+    reader.take(buf.len() as u64).read_to_end(&mut bytes)?;
+    buf[..bytes.len()].copy_from_slice(&bytes);
+    Ok(bytes.len())
+}
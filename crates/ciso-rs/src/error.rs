@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::index::BlockCodec;
+
+#[derive(Debug)]
+pub enum CisoError {
+    Io(io::Error),
+    BadMagic {
+        magic: [u8; 4],
+    },
+    UnsupportedVersion {
+        version: u8,
+    },
+    /// The header announces a size other than the 0x18 bytes of the format.
+    BadHeaderSize {
+        size: u32,
+    },
+    /// Block sizes must be a non-zero power of two.
+    InvalidBlockSize {
+        block_size: u32,
+    },
+    /// Index entries can't be shifted by more than 31 bits, nor CSO v2 padding reach a block size.
+    InvalidAlign {
+        align: u8,
+    },
+    /// Blocks compressed with `codec` can't be stored in the file's format.
+    UnsupportedCodec {
+        codec: BlockCodec,
+    },
+    /// A legacy image relies on a `feature` of its format which can't be read.
+    UnsupportedFeature {
+        feature: &'static str,
+    },
+    /// The index holds `actual` entries where the header requires `expected`.
+    IncompleteIndex {
+        expected: u64,
+        actual: u64,
+    },
+    /// The index of `total_blocks` blocks announced by the header doesn't fit in the file.
+    TruncatedIndex {
+        total_blocks: u64,
+        file_len: u64,
+    },
+    /// The data of `block` at `offset` lies outside of `start..=end`.
+    IndexOutOfBounds {
+        block: usize,
+        offset: u64,
+        start: u64,
+        end: u64,
+    },
+    /// The index entry of `block` points before the one of the previous block.
+    NonMonotonicIndex {
+        block: usize,
+        offset: u64,
+        prev_offset: u64,
+    },
+    CorruptBlock {
+        block: usize,
+        offset: u64,
+    },
+    SizeMismatch {
+        block: usize,
+        expected: u64,
+        actual: u64,
+    },
+    /// `offset` cannot be stored in a 31 bits index entry with the given `align` shift.
+    ImageTooLarge {
+        offset: u64,
+        align: u8,
+    },
+    InvalidOption {
+        name: &'static str,
+        value: u64,
+    },
+    /// `count` sectors starting at `lba` reach past the end of the image.
+    SectorsOutOfRange {
+        lba: u64,
+        count: usize,
+    },
+    Cancelled,
+    /// Zlib failed to compress `block`, or its output didn't fit the buffer.
+    CompressFailed {
+        block: usize,
+    },
+    /// A worker thread panicked, while compressing `block` when known.
+    WorkerPanicked {
+        block: Option<usize>,
+    },
+}
+
+impl fmt::Display for CisoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::BadMagic { magic } => write!(f, "bad magic {magic:02x?}"),
+            Self::UnsupportedVersion { version } => write!(f, "unsupported version {version}"),
+            Self::BadHeaderSize { size } => write!(f, "bad header size {size:#x}"),
+            Self::InvalidBlockSize { block_size } => write!(f, "invalid block size {block_size}"),
+            Self::InvalidAlign { align } => write!(f, "invalid index alignment {align}"),
+            Self::UnsupportedFeature { feature } => write!(f, "unsupported {feature}"),
+            Self::UnsupportedCodec { codec } => {
+                write!(f, "{codec:?} blocks are not supported by this format")
+            }
+            Self::IncompleteIndex { expected, actual } => write!(
+                f,
+                "incomplete index, expected {expected} entries, got {actual}"
+            ),
+            Self::TruncatedIndex {
+                total_blocks,
+                file_len,
+            } => write!(
+                f,
+                "index of {total_blocks} blocks does not fit in a {file_len} bytes file"
+            ),
+            Self::IndexOutOfBounds {
+                block,
+                offset,
+                start,
+                end,
+            } => write!(
+                f,
+                "block {block}: offset {offset:#x} outside of {start:#x}..={end:#x}"
+            ),
+            Self::NonMonotonicIndex {
+                block,
+                offset,
+                prev_offset,
+            } => write!(
+                f,
+                "non-monotonic index at block {block}: {offset:#x} < {prev_offset:#x}"
+            ),
+            Self::CorruptBlock { block, offset } => {
+                write!(f, "corrupt block {block} at offset {offset:#x}")
+            }
+            Self::SizeMismatch {
+                block,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block {block}: size mismatch, expected {expected} bytes, got {actual}"
+            ),
+            Self::ImageTooLarge { offset, align } => write!(
+                f,
+                "CSO too large: offset {offset:#x} does not fit the index with align {align}"
+            ),
+            Self::InvalidOption { name, value } => write!(f, "invalid {name}: {value}"),
+            Self::SectorsOutOfRange { lba, count } => {
+                write!(
+                    f,
+                    "{count} sectors from LBA {lba} are past the end of the image"
+                )
+            }
+            Self::Cancelled => write!(f, "operation cancelled"),
+            Self::CompressFailed { block } => write!(f, "failed to compress block {block}"),
+            Self::WorkerPanicked { block: Some(block) } => {
+                write!(f, "worker thread panicked on block {block}")
+            }
+            Self::WorkerPanicked { block: None } => write!(f, "worker thread panicked"),
+        }
+    }
+}
+
+impl Error for CisoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CisoError {
+    fn from(err: io::Error) -> Self {
+        // Unwrap errors that went through an `io::Error`, e.g. from a `Read` impl
+        if err
+            .get_ref()
+            .is_some_and(<dyn Error + Send + Sync>::is::<CisoError>)
+        {
+            let inner = err.into_inner().unwrap(); // Safety: checked above
+            return *inner.downcast::<CisoError>().unwrap(); // Safety: checked above
+        }
+
+        Self::Io(err)
+    }
+}
+
+impl From<CisoError> for io::Error {
+    fn from(err: CisoError) -> Self {
+        match err {
+            CisoError::Io(err) => err,
+            // Not `Interrupted`, which `read_exact`, `io::copy`... retry instead of stopping
+            CisoError::Cancelled => io::Error::other(err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::block::decode_block;
+use crate::cache::{BlockCache, CacheConfig, CacheStats};
+use crate::ciso_header::CisoHeader;
+use crate::error::CisoError;
+use crate::index::CisoIndex;
+
+pub const SECTOR_SIZE: usize = 0x800;
+
+const FILL_CHUNK: usize = 64; // Max blocks fetched with a single read when filling the cache
+
+/// Shareable handle over a CSO image.
+///
+/// Reads go through positional file I/O and never move a shared cursor,
+/// so a single `CisoImage` can serve many threads without locking.
+pub struct CisoImage {
+    file: File,
+    header: CisoHeader,
+    index: CisoIndex,
+    cache: Option<BlockCache>,
+    read_ahead: usize,
+    last_block: AtomicUsize, // Last block touched by a read + 1, 0 = none
+}
+
+impl CisoImage {
+    pub fn open(file: File) -> Result<Self, CisoError> {
+        Self::open_inner(file, None)
+    }
+
+    /// Opens the image with a decompressed block cache, and optional read-ahead.
+    pub fn with_cache(file: File, config: CacheConfig) -> Result<Self, CisoError> {
+        Self::open_inner(file, Some(config))
+    }
+
+    fn open_inner(file: File, config: Option<CacheConfig>) -> Result<Self, CisoError> {
+        let mut reader = PositionalReader {
+            file: &file,
+            pos: 0,
+        };
+
+        let header = CisoHeader::read_from(&mut reader)?;
+
+        let index = CisoIndex::read_from(&mut reader, &header, file.metadata()?.len())?;
+
+        let cache = config.map(|c| BlockCache::new(c.capacity_in_blocks(header.block_size)));
+        let read_ahead = config.map_or(0, |c| c.read_ahead);
+
+        Ok(Self {
+            file,
+            header,
+            index,
+            cache,
+            read_ahead,
+            last_block: AtomicUsize::new(0),
+        })
+    }
+
+    #[must_use]
+    pub fn header(&self) -> &CisoHeader {
+        &self.header
+    }
+
+    /// Block index, to inspect how the image is laid out in the file.
+    #[must_use]
+    pub fn index(&self) -> &CisoIndex {
+        &self.index
+    }
+
+    /// Size of the decompressed image.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.header.total_bytes
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.header.total_bytes == 0
+    }
+
+    /// Cache hit and miss counters, all zero when no cache is configured.
+    #[must_use]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache
+            .as_ref()
+            .map(BlockCache::stats)
+            .unwrap_or_default()
+    }
+
+    /// Reads decompressed bytes starting at `offset`, returning how many were read.
+    ///
+    /// Fewer bytes than requested are only returned at the end of the image.
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, CisoError> {
+        let block_size = u64::from(self.header.block_size);
+        let mut in_buf = Vec::new();
+        let mut block = Vec::with_capacity(self.header.block_size as usize);
+        let mut pos = offset;
+        let mut done = 0;
+
+        while done < buf.len() && pos < self.header.total_bytes {
+            let i = (pos / block_size) as usize;
+            let in_block = (pos % block_size) as usize;
+
+            let n = if let Some(cache) = &self.cache {
+                let data = self.cached_block(cache, i, &mut in_buf)?;
+                copy_block(&data, in_block, &mut buf[done..])
+            } else {
+                decode_block(&self.index, i, &mut in_buf, &mut block, |off, buf| {
+                    read_file_exact_at(&self.file, off, buf)
+                })?;
+                copy_block(&block, in_block, &mut buf[done..])
+            };
+
+            done += n;
+            pos += n as u64;
+        }
+
+        if let Some(cache) = &self.cache
+            && self.read_ahead > 0
+            && done > 0
+        {
+            let first = (offset / block_size) as usize;
+            let last = ((pos - 1) / block_size) as usize;
+            let prev = self.last_block.swap(last + 1, Ordering::Relaxed);
+
+            // Only read ahead when this read continues the previous one. Nobody asked for these
+            // blocks yet: those failing are left uncached, to report their error once read
+            if prev != 0 && first + 1 >= prev && first <= prev {
+                let end = (last + 1 + self.read_ahead).min(self.index.len());
+                self.fill(cache, last + 1..end).ok();
+            }
+        }
+
+        Ok(done)
+    }
+
+    /// Hints that the decompressed byte `range` will be read soon.
+    ///
+    /// The touched blocks are decoded into the cache, up to its capacity, the first error being
+    /// returned once the other blocks are cached. Does nothing when the image has no cache.
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn prefetch(&self, range: Range<u64>) -> Result<(), CisoError> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+
+        let block_size = u64::from(self.header.block_size);
+        let total_blocks = self.index.len();
+
+        let first = (range.start / block_size) as usize;
+        let end = (range.end.div_ceil(block_size) as usize)
+            .min(total_blocks)
+            .min(first.saturating_add(cache.capacity()));
+
+        self.fill(cache, first..end)
+    }
+
+    fn cached_block(
+        &self,
+        cache: &BlockCache,
+        i: usize,
+        in_buf: &mut Vec<u8>,
+    ) -> Result<Arc<[u8]>, CisoError> {
+        if let Some(data) = cache.get(i) {
+            return Ok(data);
+        }
+
+        let mut block = Vec::new();
+        decode_block(&self.index, i, in_buf, &mut block, |off, buf| {
+            read_file_exact_at(&self.file, off, buf)
+        })?;
+
+        let data = Arc::<[u8]>::from(block);
+        cache.insert(i, data.clone());
+        Ok(data)
+    }
+
+    /// Decodes the uncached `blocks` into the cache, reading their stored bytes in batches.
+    ///
+    /// Failing blocks are skipped, and the first error returned after the others are cached.
+    #[expect(clippy::cast_possible_truncation)]
+    fn fill(&self, cache: &BlockCache, blocks: Range<usize>) -> Result<(), CisoError> {
+        let max_span = (FILL_CHUNK as u64 * u64::from(self.header.block_size)) << 1;
+        let mut in_buf = Vec::new();
+        let mut span = Vec::new();
+        let mut first_error = None;
+
+        for start in blocks.clone().step_by(FILL_CHUNK) {
+            let chunk = start..(start + FILL_CHUNK).min(blocks.end);
+            if chunk.clone().all(|i| cache.contains(i)) {
+                continue;
+            }
+
+            let span_start = self.index.offset(chunk.start);
+            let span_len = self.index.offset(chunk.end).saturating_sub(span_start);
+            // Blocks are read one by one when the span can't be, e.g. past the end of the file
+            let batched = span_len <= max_span && {
+                span.resize(span_len as usize, 0);
+                read_file_exact_at(&self.file, span_start, &mut span).is_ok()
+            };
+
+            for i in chunk {
+                if cache.contains(i) {
+                    continue;
+                }
+
+                let mut block = Vec::new();
+                let decoded = decode_block(&self.index, i, &mut in_buf, &mut block, |off, buf| {
+                    if !batched {
+                        return read_file_exact_at(&self.file, off, buf);
+                    }
+
+                    let start = off
+                        .checked_sub(span_start)
+                        .map(|s| s as usize)
+                        .filter(|s| s + buf.len() <= span.len())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "block outside of index span",
+                            )
+                        })?;
+                    buf.copy_from_slice(&span[start..start + buf.len()]);
+                    Ok(())
+                });
+
+                match decoded {
+                    Ok(_) => cache.insert(i, Arc::from(block)),
+                    Err(error) => {
+                        first_error.get_or_insert(error);
+                    }
+                }
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+
+    pub fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), CisoError> {
+        if self.read_at(offset, buf)? != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "read past the end of the image",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Reads `count` consecutive 2 KiB sectors starting at `lba`.
+    ///
+    /// Ranges reaching past the end of the image are rejected before anything is allocated.
+    pub fn read_sectors(&self, lba: u64, count: usize) -> Result<Vec<u8>, CisoError> {
+        let out_of_range = || CisoError::SectorsOutOfRange { lba, count };
+
+        let offset = lba
+            .checked_mul(SECTOR_SIZE as u64)
+            .ok_or_else(out_of_range)?;
+        let len = count.checked_mul(SECTOR_SIZE).ok_or_else(out_of_range)?;
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > self.len())
+        {
+            return Err(out_of_range());
+        }
+
+        let mut buf = vec![0u8; len];
+        self.read_exact_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+fn copy_block(block: &[u8], in_block: usize, dst: &mut [u8]) -> usize {
+    let n = (block.len() - in_block).min(dst.len());
+    dst[..n].copy_from_slice(&block[in_block..in_block + n]);
+    n
+}
+
+struct PositionalReader<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl io::Read for PositionalReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+fn read_file_exact_at(file: &File, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ));
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
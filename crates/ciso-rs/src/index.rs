@@ -0,0 +1,340 @@
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use crate::ciso_header::{CisoFormat, CisoHeader};
+use crate::error::CisoError;
+
+/// Set on the raw entries of blocks stored without compression, or with LZ4 in CSO v2.
+const HIGH_FLAG: u32 = 0x8000_0000;
+/// Bits of the raw entries holding the block position, shifted right by the header's `align`.
+const OFFSET_MASK: u32 = 0x7fff_ffff;
+
+/// How a block is stored in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockCodec {
+    Plain,
+    Deflate, // Raw deflate stream, without zlib header
+    Lz4,     // Raw LZ4 block, without frame
+    Zlib,    // Deflate stream with its zlib header, as found in DAX and JSO images
+    Lzo,     // LZO1X stream, as found in JSO images
+}
+
+/// Where and how a block is stored in the CSO file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockEntry {
+    pub offset: u64,     // Position of the stored block in the file
+    pub stored_len: u64, // Bytes up to the next block, alignment padding included
+    pub is_plain: bool,
+}
+
+/// Block index of a CSO file, one entry per block plus one for the end of the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CisoIndex {
+    header: CisoHeader,
+    entries: Vec<u32>,
+}
+
+impl CisoIndex {
+    /// Empty index for `header`, filled in block order with `push`.
+    #[must_use]
+    pub fn new(header: CisoHeader) -> Self {
+        Self {
+            header,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Reads the index following the header, rejecting indexes larger than the file before allocating them.
+    pub fn read_from(
+        r: &mut impl Read,
+        header: &CisoHeader,
+        file_len: u64,
+    ) -> Result<Self, CisoError> {
+        let total_blocks = header.total_blocks();
+
+        let entries = total_blocks
+            .checked_add(1)
+            .filter(|entries| {
+                entries
+                    .checked_mul(4)
+                    .and_then(|len| len.checked_add(CisoHeader::SIZE as u64))
+                    .is_some_and(|end| end <= file_len)
+            })
+            .and_then(|entries| usize::try_from(entries).ok())
+            .ok_or(CisoError::TruncatedIndex {
+                total_blocks,
+                file_len,
+            })?;
+
+        let mut index = vec![0u32; entries];
+        for v in &mut index {
+            let mut buf = [0u8; 4];
+            r.read_exact(&mut buf)?;
+            *v = u32::from_le_bytes(buf);
+        }
+
+        Ok(Self {
+            header: *header,
+            entries: index,
+        })
+    }
+
+    pub fn write_into(&self, w: &mut impl Write) -> io::Result<()> {
+        for entry in &self.entries {
+            w.write_all(&entry.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends the entry of the next block, or the end of the data once every block is pushed.
+    ///
+    /// `offset` must be aligned on the header's `align`. Blocks which aren't plain are LZ4 ones
+    /// for ZSO, deflate ones otherwise.
+    pub fn push(&mut self, offset: u64, is_plain: bool) -> Result<(), CisoError> {
+        let codec = match (is_plain, self.header.format()) {
+            (true, _) => BlockCodec::Plain,
+            (false, CisoFormat::Zso) => BlockCodec::Lz4,
+            (false, CisoFormat::Cso | CisoFormat::Cso2) => BlockCodec::Deflate,
+        };
+        self.push_codec(offset, codec)
+    }
+
+    /// Appends the entry of the next block, stored with `codec`.
+    ///
+    /// CSO v2 plain blocks are told by their size, they must be stored in full.
+    pub fn push_codec(&mut self, offset: u64, codec: BlockCodec) -> Result<(), CisoError> {
+        let flagged = match (self.header.format(), codec) {
+            (CisoFormat::Cso | CisoFormat::Zso, BlockCodec::Plain)
+            | (CisoFormat::Cso2, BlockCodec::Lz4) => true,
+            (CisoFormat::Cso | CisoFormat::Cso2, BlockCodec::Deflate)
+            | (CisoFormat::Cso2, BlockCodec::Plain)
+            | (CisoFormat::Zso, BlockCodec::Lz4) => false,
+            (CisoFormat::Cso, BlockCodec::Lz4)
+            | (CisoFormat::Zso, BlockCodec::Deflate)
+            | (_, BlockCodec::Zlib | BlockCodec::Lzo) => {
+                return Err(CisoError::UnsupportedCodec { codec });
+            }
+        };
+
+        let align = self.header.align;
+        let entry = offset >> align;
+        if entry > u64::from(OFFSET_MASK) {
+            return Err(CisoError::ImageTooLarge { offset, align });
+        }
+
+        #[expect(clippy::cast_possible_truncation)] // Checked above
+        let entry = entry as u32;
+        self.entries
+            .push(if flagged { entry | HIGH_FLAG } else { entry });
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn header(&self) -> &CisoHeader {
+        &self.header
+    }
+
+    /// Entries as stored in the file, with the high flag and the shifted offset.
+    #[must_use]
+    pub fn raw(&self) -> &[u32] {
+        &self.entries
+    }
+
+    /// Number of blocks described by the index.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len().saturating_sub(1)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Position of the first block, right after the header and a complete index.
+    #[must_use]
+    pub fn data_start(&self) -> u64 {
+        (self.header.total_blocks().saturating_add(1))
+            .saturating_mul(4)
+            .saturating_add(CisoHeader::SIZE as u64)
+    }
+
+    /// Position of block `i` in the file, `i == len()` giving the end of the data.
+    ///
+    /// `i` must be at most `len()`.
+    #[must_use]
+    pub fn offset(&self, i: usize) -> u64 {
+        u64::from(self.entries[i] & OFFSET_MASK) << self.header.align
+    }
+
+    /// `i` must be lower than `len()`.
+    #[must_use]
+    pub fn is_plain(&self, i: usize) -> bool {
+        self.codec(i) == BlockCodec::Plain
+    }
+
+    /// `i` must be lower than `len()`.
+    #[must_use]
+    pub fn codec(&self, i: usize) -> BlockCodec {
+        let flagged = self.entries[i] & HIGH_FLAG != 0;
+
+        match self.header.format() {
+            CisoFormat::Cso | CisoFormat::Zso if flagged => BlockCodec::Plain,
+            CisoFormat::Cso => BlockCodec::Deflate,
+            CisoFormat::Zso => BlockCodec::Lz4,
+            CisoFormat::Cso2 => {
+                let range = self.block_range(i);
+                let stored = self.offset(i + 1).saturating_sub(self.offset(i));
+                if stored >= range.end - range.start {
+                    BlockCodec::Plain
+                } else if flagged {
+                    BlockCodec::Lz4
+                } else {
+                    BlockCodec::Deflate
+                }
+            }
+        }
+    }
+
+    /// Entry of block `i`, `stored_len` is zero when the next entry goes backwards.
+    #[must_use]
+    pub fn entry(&self, i: usize) -> Option<BlockEntry> {
+        if i >= self.len() {
+            return None;
+        }
+
+        let offset = self.offset(i);
+        Some(BlockEntry {
+            offset,
+            stored_len: self.offset(i + 1).saturating_sub(offset),
+            is_plain: self.is_plain(i),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = BlockEntry> + '_ {
+        (0..self.len()).filter_map(|i| self.entry(i))
+    }
+
+    /// Block holding the decompressed `byte` of the image.
+    #[must_use]
+    pub fn block_for_offset(&self, byte: u64) -> Option<usize> {
+        if byte >= self.header.total_bytes {
+            return None;
+        }
+
+        usize::try_from(byte / u64::from(self.header.block_size)).ok()
+    }
+
+    /// Decompressed bytes of the image held by block `i`.
+    #[must_use]
+    pub fn block_range(&self, i: usize) -> Range<u64> {
+        let block_size = u64::from(self.header.block_size);
+        let start = (i as u64)
+            .saturating_mul(block_size)
+            .min(self.header.total_bytes);
+        start
+            ..start
+                .saturating_add(block_size)
+                .min(self.header.total_bytes)
+    }
+
+    /// Checks the index is complete, monotonic and within a file of `file_len` bytes.
+    ///
+    /// Returns the first inconsistency found, in block order.
+    pub fn validate(&self, file_len: u64) -> Result<(), CisoError> {
+        let total_blocks = self.header.total_blocks();
+        if self.entries.len() as u64 != total_blocks.saturating_add(1) {
+            return Err(CisoError::IncompleteIndex {
+                expected: total_blocks.saturating_add(1),
+                actual: self.entries.len() as u64,
+            });
+        }
+
+        let end = self.offset(self.len());
+        if end > file_len {
+            return Err(CisoError::IndexOutOfBounds {
+                block: self.len(),
+                offset: end,
+                start: self.data_start(),
+                end: file_len,
+            });
+        }
+
+        let mut prev_off = self.data_start();
+        for i in 0..self.len() {
+            prev_off = self.check_entry(i, prev_off, file_len)?.offset;
+        }
+
+        Ok(())
+    }
+
+    /// Structural checks of block `i`, whose predecessor is stored at `prev_off`.
+    ///
+    /// The returned `stored_len` excludes the alignment padding of plain blocks.
+    pub(crate) fn check_entry(
+        &self,
+        i: usize,
+        prev_off: u64,
+        file_len: u64,
+    ) -> Result<BlockEntry, CisoError> {
+        let data_start = self.data_start();
+        let is_plain = self.is_plain(i);
+
+        let off = self.offset(i);
+        let next = self.offset(i + 1);
+
+        if off < data_start {
+            return Err(CisoError::IndexOutOfBounds {
+                block: i,
+                offset: off,
+                start: data_start,
+                end: file_len,
+            });
+        }
+
+        if off < prev_off {
+            return Err(CisoError::NonMonotonicIndex {
+                block: i,
+                offset: off,
+                prev_offset: prev_off,
+            });
+        }
+
+        let range = self.block_range(i);
+        let expected_size = range.end - range.start;
+
+        let stored = next.checked_sub(off).ok_or(CisoError::NonMonotonicIndex {
+            block: i + 1,
+            offset: next,
+            prev_offset: off,
+        })?;
+
+        // Plain blocks may be followed by alignment padding
+        let max_padding = (1u64 << self.header.align) - 1;
+        if is_plain && !(expected_size..=expected_size + max_padding).contains(&stored) {
+            return Err(CisoError::SizeMismatch {
+                block: i,
+                expected: expected_size,
+                actual: stored,
+            });
+        }
+
+        let stored_len = if is_plain { expected_size } else { stored };
+
+        if off + stored_len > file_len {
+            return Err(CisoError::IndexOutOfBounds {
+                block: i,
+                offset: off + stored_len,
+                start: data_start,
+                end: file_len,
+            });
+        }
+
+        Ok(BlockEntry {
+            offset: off,
+            stored_len,
+            is_plain,
+        })
+    }
+}
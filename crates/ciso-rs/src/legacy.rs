@@ -0,0 +1,545 @@
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::block::decode_stored;
+use crate::check::{CheckOptions, FaultyBlock};
+use crate::compress::{CompressOptions, compress_ciso_stream};
+use crate::decompress::DecompressOptions;
+use crate::error::CisoError;
+use crate::index::BlockCodec;
+use crate::progress::Progress;
+use crate::reader::BlockCursor;
+
+/// Decompressed size of a DAX frame.
+const DAX_FRAME_SIZE: u32 = 0x2000;
+const DAX_HEADER_SIZE: usize = 0x20;
+const JSO_HEADER_SIZE: usize = 0x30;
+
+/// Compressed image format predating CSO, read but never written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyFormat {
+    /// `DAX\0` magic: zlib frames of 8 KiB, with a table of stored sizes and uncompressed areas.
+    Dax,
+    /// `JISO` magic: LZO or zlib blocks, plain ones being stored on their full size.
+    Jso,
+}
+
+/// Where a block of a legacy image is stored, and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LegacyBlock {
+    pub offset: u64,
+    pub stored_len: u64,
+    pub codec: BlockCodec,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    end: u64, // May be before `offset` in a damaged JSO index
+    codec: BlockCodec,
+}
+
+/// Block table of a DAX or JSO image, the counterpart of `CisoIndex`.
+#[derive(Debug, Clone)]
+pub struct LegacyIndex {
+    format: LegacyFormat,
+    total_bytes: u64,
+    block_size: u32,
+    data_start: u64,
+    file_len: u64,
+    entries: Vec<Entry>,
+}
+
+impl LegacyIndex {
+    /// Reads the header and block table of a DAX or JSO image, told apart by their magic.
+    ///
+    /// Tables which can't fit in the file are rejected before being allocated.
+    pub fn read_from(r: &mut (impl Read + Seek)) -> Result<Self, CisoError> {
+        let file_len = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(0))?;
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+
+        match &magic {
+            b"DAX\0" => Self::read_dax(r, file_len),
+            b"JISO" => Self::read_jso(r, file_len),
+            _ => Err(CisoError::BadMagic { magic }),
+        }
+    }
+
+    /// The DAX header holds the image size, the version and the number of uncompressed areas
+    /// (u32 each), then 16 reserved bytes. The frame offsets (u32) and stored sizes (u16)
+    /// follow, then for version 1 the uncompressed areas as (first frame, frames) u32 pairs.
+    fn read_dax(r: &mut impl Read, file_len: u64) -> Result<Self, CisoError> {
+        let mut header = [0u8; DAX_HEADER_SIZE - 4];
+        r.read_exact(&mut header)?;
+
+        let total_bytes = u64::from(le_u32(&header, 0x00));
+        let version = le_u32(&header, 0x04);
+        if version > 1 {
+            return Err(CisoError::UnsupportedVersion {
+                version: u8::try_from(version).unwrap_or(u8::MAX),
+            });
+        }
+        // Version 0 images have no uncompressed areas, whatever the header says
+        let nc_areas = if version == 1 {
+            u64::from(le_u32(&header, 0x08))
+        } else {
+            0
+        };
+
+        let total_blocks = total_bytes.div_ceil(u64::from(DAX_FRAME_SIZE));
+        let data_start = DAX_HEADER_SIZE as u64 + total_blocks * 6 + nc_areas * 8;
+        if data_start > file_len {
+            return Err(CisoError::TruncatedIndex {
+                total_blocks,
+                file_len,
+            });
+        }
+
+        #[expect(clippy::cast_possible_truncation)] // Bounded by the file length
+        let (total_blocks, nc_areas) = (total_blocks as usize, nc_areas as usize);
+        let offsets = read_table(r, total_blocks * 4)?;
+        let lengths = read_table(r, total_blocks * 2)?;
+        let areas = read_table(r, nc_areas * 8)?;
+
+        let mut plain = vec![false; total_blocks];
+        for area in areas.chunks_exact(8) {
+            let first = le_u32(area, 0) as usize;
+            let frames = le_u32(area, 4) as usize;
+            let end = first.saturating_add(frames).min(total_blocks);
+            if let Some(frames) = plain.get_mut(first..end) {
+                frames.fill(true);
+            }
+        }
+
+        let mut index = Self {
+            format: LegacyFormat::Dax,
+            total_bytes,
+            block_size: DAX_FRAME_SIZE,
+            data_start,
+            file_len,
+            entries: Vec::with_capacity(total_blocks),
+        };
+        for i in 0..total_blocks {
+            let offset = u64::from(le_u32(&offsets, i * 4));
+            let entry = if plain[i] {
+                let range = index.block_range(i);
+                Entry {
+                    offset,
+                    end: offset + (range.end - range.start),
+                    codec: BlockCodec::Plain,
+                }
+            } else {
+                let len = u16::from_le_bytes([lengths[i * 2], lengths[i * 2 + 1]]);
+                Entry {
+                    offset,
+                    end: offset + u64::from(len),
+                    codec: BlockCodec::Zlib,
+                }
+            };
+            index.entries.push(entry);
+        }
+
+        Ok(index)
+    }
+
+    /// The JSO header, as documented by maxcso, holds two unknown bytes, the block size (u16),
+    /// a block headers flag, an unknown byte, the method (0 for LZO, 1 for zlib), an unknown
+    /// byte, the image size (u32) and its MD5, reserved bytes filling it up to 0x30. The u32
+    /// block offsets follow, with one more entry for the end of the data.
+    fn read_jso(r: &mut impl Read, file_len: u64) -> Result<Self, CisoError> {
+        let mut header = [0u8; JSO_HEADER_SIZE - 4];
+        r.read_exact(&mut header)?;
+
+        let block_size = u32::from(u16::from_le_bytes([header[0x02], header[0x03]]));
+        if !block_size.is_power_of_two() {
+            return Err(CisoError::InvalidBlockSize { block_size });
+        }
+        if header[0x04] != 0 {
+            return Err(CisoError::UnsupportedFeature {
+                feature: "JSO block headers",
+            });
+        }
+        let codec = match header[0x06] {
+            0 => BlockCodec::Lzo,
+            1 => BlockCodec::Zlib,
+            _ => {
+                return Err(CisoError::UnsupportedFeature {
+                    feature: "JSO compression method",
+                });
+            }
+        };
+        let total_bytes = u64::from(le_u32(&header, 0x08));
+
+        let total_blocks = total_bytes.div_ceil(u64::from(block_size));
+        let data_start = JSO_HEADER_SIZE as u64 + (total_blocks + 1) * 4;
+        if data_start > file_len {
+            return Err(CisoError::TruncatedIndex {
+                total_blocks,
+                file_len,
+            });
+        }
+
+        #[expect(clippy::cast_possible_truncation)] // Bounded by the file length
+        let total_blocks = total_blocks as usize;
+        let offsets = read_table(r, (total_blocks + 1) * 4)?;
+
+        let mut index = Self {
+            format: LegacyFormat::Jso,
+            total_bytes,
+            block_size,
+            data_start,
+            file_len,
+            entries: Vec::with_capacity(total_blocks),
+        };
+        for i in 0..total_blocks {
+            let offset = u64::from(le_u32(&offsets, i * 4));
+            let end = u64::from(le_u32(&offsets, (i + 1) * 4));
+            let range = index.block_range(i);
+            // Blocks which didn't compress are stored as is
+            let codec = if end.saturating_sub(offset) >= range.end - range.start {
+                BlockCodec::Plain
+            } else {
+                codec
+            };
+            index.entries.push(Entry { offset, end, codec });
+        }
+
+        Ok(index)
+    }
+
+    #[must_use]
+    pub fn format(&self) -> LegacyFormat {
+        self.format
+    }
+
+    /// Size of the decompressed image.
+    #[must_use]
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    #[must_use]
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    /// Number of blocks of the image.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Position of the first block, right after the header and tables.
+    #[must_use]
+    pub fn data_start(&self) -> u64 {
+        self.data_start
+    }
+
+    /// `i` must be lower than `len()`.
+    #[must_use]
+    pub fn block(&self, i: usize) -> LegacyBlock {
+        let entry = self.entries[i];
+        LegacyBlock {
+            offset: entry.offset,
+            stored_len: entry.end.saturating_sub(entry.offset),
+            codec: entry.codec,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = LegacyBlock> + '_ {
+        (0..self.len()).map(|i| self.block(i))
+    }
+
+    /// Bytes of the decompressed image held by block `i`, the last one being shorter for
+    /// images which aren't a multiple of the block size.
+    #[must_use]
+    pub fn block_range(&self, i: usize) -> Range<u64> {
+        let block_size = u64::from(self.block_size);
+        let start = i as u64 * block_size;
+        start..(start + block_size).min(self.total_bytes)
+    }
+
+    /// Structural checks of block `i`: stored within the file, and no larger than a
+    /// compressed block can be.
+    ///
+    /// `i` must be lower than `len()`.
+    pub fn check_block(&self, i: usize) -> Result<LegacyBlock, CisoError> {
+        let entry = self.entries[i];
+
+        if entry.end < entry.offset {
+            return Err(CisoError::NonMonotonicIndex {
+                block: i + 1,
+                offset: entry.end,
+                prev_offset: entry.offset,
+            });
+        }
+
+        if entry.offset < self.data_start || entry.end > self.file_len {
+            return Err(CisoError::IndexOutOfBounds {
+                block: i,
+                offset: entry.offset,
+                start: self.data_start,
+                end: self.file_len,
+            });
+        }
+
+        let block = self.block(i);
+        if block.codec != BlockCodec::Plain && block.stored_len > u64::from(self.block_size) * 2 {
+            return Err(CisoError::CorruptBlock {
+                block: i,
+                offset: block.offset,
+            });
+        }
+
+        Ok(block)
+    }
+}
+
+/// Random access over the decompressed content of a DAX or JSO image, like `CisoReader`.
+pub struct LegacyReader<R> {
+    inner: R,
+    index: LegacyIndex,
+    in_buf: Vec<u8>,
+    cursor: BlockCursor,
+}
+
+impl<R: Read + Seek> LegacyReader<R> {
+    pub fn new(mut inner: R) -> Result<Self, CisoError> {
+        let index = LegacyIndex::read_from(&mut inner)?;
+        let block_size = index.block_size as usize;
+
+        Ok(Self {
+            inner,
+            cursor: BlockCursor::new(index.total_bytes, index.block_size),
+            index,
+            in_buf: Vec::with_capacity(block_size * 2),
+        })
+    }
+
+    /// Block table, to inspect how the image is laid out in the file.
+    #[must_use]
+    pub fn index(&self) -> &LegacyIndex {
+        &self.index
+    }
+
+    /// Size of the decompressed image.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.index.total_bytes
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.total_bytes == 0
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn load_block(&mut self, i: usize) -> Result<&[u8], CisoError> {
+        let (inner, index, in_buf) = (&mut self.inner, &self.index, &mut self.in_buf);
+        self.cursor
+            .load(i, |i, block| load_block(index, i, in_buf, block, inner))
+    }
+}
+
+impl<R: Read + Seek> Read for LegacyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (inner, index, in_buf) = (&mut self.inner, &self.index, &mut self.in_buf);
+        self.cursor
+            .read(buf, |i, block| load_block(index, i, in_buf, block, inner))
+    }
+}
+
+impl<R: Read + Seek> Seek for LegacyReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+/// Decodes block `i` into `out`, resized to the block's decompressed size.
+#[expect(clippy::cast_possible_truncation)]
+fn load_block(
+    index: &LegacyIndex,
+    i: usize,
+    in_buf: &mut Vec<u8>,
+    out: &mut Vec<u8>,
+    inner: &mut (impl Read + Seek),
+) -> Result<(), CisoError> {
+    let block = index.check_block(i)?;
+    let range = index.block_range(i);
+    out.resize((range.end - range.start) as usize, 0);
+
+    let stored_len = if block.codec == BlockCodec::Plain {
+        out.len()
+    } else {
+        block.stored_len as usize
+    };
+    in_buf.resize(stored_len, 0);
+    inner.seek(SeekFrom::Start(block.offset))?;
+    inner.read_exact(in_buf)?;
+
+    decode_stored(block.codec, in_buf, out, i, block.offset)?;
+    Ok(())
+}
+
+/// Outcome of `check_legacy`, the counterpart of `CheckReport`.
+#[derive(Debug)]
+pub struct LegacyReport {
+    pub format: LegacyFormat,
+    pub total_bytes: u64,
+    pub block_size: u32,
+    pub total_blocks: usize,
+    pub plain_blocks: usize,
+    pub compressed_blocks: usize,
+    pub file_len: u64,
+    pub faulty_blocks: Vec<FaultyBlock>,
+}
+
+impl LegacyReport {
+    /// Size of the file relative to the image size, 1.0 for empty images.
+    #[must_use]
+    #[expect(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 1.0;
+        }
+
+        self.file_len as f64 / self.total_bytes as f64
+    }
+
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.faulty_blocks.is_empty()
+    }
+}
+
+/// Checks every block of a DAX or JSO image, decompressing them in full mode.
+///
+/// Blocks are checked in order on the calling thread, `threads` is ignored. Only an unreadable
+/// header or table, and cancellations, are returned as errors.
+pub fn check_legacy(
+    input: impl Read + Seek,
+    options: &CheckOptions,
+) -> Result<LegacyReport, CisoError> {
+    options.validate()?;
+
+    let mut reader = LegacyReader::new(input)?;
+    let total_blocks = reader.index.len();
+
+    let mut faulty_blocks = Vec::new();
+    let mut bytes_in = 0;
+    let mut bytes_out = 0;
+
+    for i in 0..total_blocks {
+        options.cancel.check()?;
+
+        let range = reader.index.block_range(i);
+        let checked = reader.index.check_block(i).and_then(|block| {
+            if options.full && block.codec != BlockCodec::Plain {
+                reader.load_block(i)?;
+            }
+            Ok(block)
+        });
+
+        match checked {
+            Ok(block) => bytes_in += block.stored_len,
+            Err(error) => faulty_blocks.push(FaultyBlock {
+                block: i,
+                range: range.clone(),
+                error,
+            }),
+        }
+        bytes_out += range.end - range.start;
+
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in,
+            bytes_out,
+        });
+    }
+
+    let index = reader.index;
+    let plain_blocks = index
+        .iter()
+        .filter(|block| block.codec == BlockCodec::Plain)
+        .count();
+
+    Ok(LegacyReport {
+        format: index.format,
+        total_bytes: index.total_bytes,
+        block_size: index.block_size,
+        total_blocks,
+        plain_blocks,
+        compressed_blocks: total_blocks - plain_blocks,
+        file_len: index.file_len,
+        faulty_blocks,
+    })
+}
+
+/// Decompresses a DAX or JSO image into any output.
+///
+/// Blocks are decoded in order on the calling thread, `threads` is ignored.
+pub fn decompress_legacy(
+    input: impl Read + Seek,
+    output: impl Write,
+    options: &DecompressOptions,
+) -> Result<(), CisoError> {
+    options.validate()?;
+
+    let mut reader = LegacyReader::new(input)?;
+    let total_blocks = reader.index.len();
+
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    let mut bytes_in = 0;
+
+    for i in 0..total_blocks {
+        options.cancel.check()?;
+
+        writer.write_all(reader.load_block(i)?)?;
+        bytes_in += reader.index.block(i).stored_len;
+
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in,
+            bytes_out: reader.index.block_range(i).end,
+        });
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Converts a DAX or JSO image into a CSO, without going through a plain image.
+///
+/// Blocks are decoded and compressed in order on the calling thread, like
+/// `compress_ciso_stream`.
+pub fn convert_legacy(
+    input: impl Read + Seek,
+    output: impl Write + Seek,
+    options: &CompressOptions,
+) -> Result<(), CisoError> {
+    compress_ciso_stream(LegacyReader::new(input)?, output, options)
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_table(r: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut table = vec![0u8; len];
+    r.read_exact(&mut table)?;
+    Ok(table)
+}
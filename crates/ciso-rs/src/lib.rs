@@ -1,11 +1,48 @@
 #![expect(clippy::missing_errors_doc)]
 
-pub use check::check_ciso;
-pub use ciso_header::CisoHeader;
-pub use compress::compress_ciso;
-pub use decompress::decompress_ciso;
+pub use cache::{CacheCapacity, CacheConfig, CacheStats};
+pub use cancel::CancellationToken;
+pub use check::{
+    CheckOptions, CheckReport, FaultyBlock, check_ciso, check_ciso_report, check_ciso_stream,
+    check_ciso_with,
+};
+pub use ciso_header::{CisoFormat, CisoHeader};
+pub use compress::{
+    CompressOptions, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, compress_ciso, compress_ciso_spooled,
+    compress_ciso_stream, compress_ciso_with,
+};
+pub use decompress::{
+    DecompressOptions, decompress_ciso, decompress_ciso_stream, decompress_ciso_with,
+};
+pub use detect::{ImageFormat, detect_format};
+pub use error::CisoError;
+pub use image::{CisoImage, SECTOR_SIZE};
+pub use index::{BlockCodec, BlockEntry, CisoIndex};
+pub use legacy::{
+    LegacyBlock, LegacyFormat, LegacyIndex, LegacyReader, LegacyReport, check_legacy,
+    convert_legacy, decompress_legacy,
+};
+pub use progress::{Progress, ProgressObserver};
+pub use reader::CisoReader;
+pub use recompress::{RecompressOptions, RecompressReport, recompress_ciso};
+pub use repair::{RepairOptions, RepairReport, repair_ciso};
 
+mod block;
+mod cache;
+mod cancel;
 mod check;
 mod ciso_header;
 mod compress;
 mod decompress;
+mod detect;
+mod error;
+mod image;
+mod index;
+mod legacy;
+mod lz4;
+mod lzo;
+mod progress;
+mod queue;
+mod reader;
+mod recompress;
+mod repair;
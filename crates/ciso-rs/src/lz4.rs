@@ -0,0 +1,118 @@
+use std::io;
+
+use lz4::block::{self, CompressionMode};
+
+/// Compresses `input` into a raw LZ4 block, returning its size.
+///
+/// Level 0 uses the fast compressor, higher levels LZ4 HC.
+#[expect(clippy::cast_possible_wrap)] // Levels are validated by the options
+pub(crate) fn compress(input: &[u8], out: &mut [u8], level: u32) -> io::Result<usize> {
+    let mode = match level {
+        0 => CompressionMode::DEFAULT,
+        level => CompressionMode::HIGHCOMPRESSION(level as i32),
+    };
+    block::compress_to_buffer(input, Some(mode), false, out)
+}
+
+/// Largest raw LZ4 block produced for an input of `len` bytes.
+pub(crate) fn max_compressed_size(len: usize) -> usize {
+    // Same bound as `LZ4_compressBound`
+    len + len / 255 + 16
+}
+
+/// Decodes a raw LZ4 block into `out`, returning the size of the block at the start of `input`
+/// and the decoded size, or `None` when malformed.
+///
+/// Decoding stops as soon as `out` is full, so that the alignment padding following the
+/// block is ignored.
+pub(crate) fn decompress(input: &[u8], out: &mut [u8]) -> Option<(usize, usize)> {
+    let mut ip = 0;
+    let mut op = 0;
+
+    loop {
+        let token = *input.get(ip)?;
+        ip += 1;
+
+        let literals = read_len(input, &mut ip, usize::from(token >> 4))?;
+        let src = input.get(ip..ip.checked_add(literals)?)?;
+        out.get_mut(op..op + literals)?.copy_from_slice(src);
+        ip += literals;
+        op += literals;
+
+        // The last sequence only holds literals
+        if ip == input.len() || op == out.len() {
+            return Some((ip, op));
+        }
+
+        let offset = usize::from(u16::from_le_bytes([*input.get(ip)?, *input.get(ip + 1)?]));
+        ip += 2;
+        if offset == 0 || offset > op {
+            return None;
+        }
+
+        let len = read_len(input, &mut ip, usize::from(token & 0xf))? + 4;
+        if len > out.len() - op {
+            return None;
+        }
+
+        // Byte by byte, overlapping matches repeat their pattern
+        for k in op..op + len {
+            out[k] = out[k - offset];
+        }
+        op += len;
+    }
+}
+
+/// Length stored in a token nibble, extended by the following bytes when saturated.
+fn read_len(input: &[u8], ip: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 0xf {
+        loop {
+            let byte = *input.get(*ip)?;
+            *ip += 1;
+            len = len.checked_add(usize::from(byte))?;
+            if byte != 0xff {
+                break;
+            }
+        }
+    }
+
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_blocks_roundtrip() {
+        let data: Vec<u8> = (0..0x800u32)
+            .map(|i| u8::try_from(i % 251 / 7).unwrap())
+            .collect();
+
+        for level in [0, 9] {
+            let mut buf = vec![0u8; max_compressed_size(data.len()) + 64];
+            let size = compress(&data, &mut buf, level).unwrap();
+            assert!(size < data.len());
+
+            // Zeroed alignment padding after the block
+            let mut out = vec![0u8; data.len()];
+            assert_eq!(
+                decompress(&buf[..size + 64], &mut out),
+                Some((size, data.len()))
+            );
+            assert_eq!(out, data);
+        }
+    }
+
+    #[test]
+    fn malformed_blocks_are_rejected() {
+        let mut out = [0u8; 16];
+        // Match before any output
+        assert_eq!(decompress(&[0x04, 0x01, 0x00], &mut out), None);
+        // Literals past the end of the input
+        assert_eq!(decompress(&[0x50, b'a'], &mut out), None);
+        // Match longer than the output
+        assert_eq!(decompress(&[0x1f, b'a', 0x01, 0x00, 0x0a], &mut out), None);
+    }
+}
@@ -0,0 +1,168 @@
+/// Decodes an LZO1X stream into `out`, returning the size of the stream at the start of `input`
+/// and the decoded size, or `None` when malformed.
+///
+/// Only decompression is supported, JSO images being read but never written.
+pub(crate) fn decompress(input: &[u8], out: &mut [u8]) -> Option<(usize, usize)> {
+    let mut ip = 0;
+    let mut op = 0;
+    // Literals copied by the last instruction, 4 standing for a literal run
+    let mut state = 0;
+
+    // A first byte above 17 starts the stream with literals
+    let first = usize::from(*input.first()?);
+    if first > 17 {
+        ip = 1;
+        let literals = first - 17;
+        copy_literals(input, &mut ip, out, &mut op, literals)?;
+        state = literals.min(4);
+    }
+
+    loop {
+        let t = usize::from(*input.get(ip)?);
+        ip += 1;
+
+        let (len, dist, literals) = match t {
+            0..16 if state == 0 => {
+                let literals = read_len(input, &mut ip, t, 15)? + 3;
+                copy_literals(input, &mut ip, out, &mut op, literals)?;
+                state = 4;
+                continue;
+            }
+            0..16 => {
+                let low = (t >> 2) + (usize::from(*input.get(ip)?) << 2);
+                ip += 1;
+                // Right after a literal run, short matches reach further back
+                if state == 4 {
+                    (3, 1 + 0x800 + low, t & 3)
+                } else {
+                    (2, 1 + low, t & 3)
+                }
+            }
+            16..32 => {
+                let len = read_len(input, &mut ip, t & 7, 7)? + 2;
+                let v = read_u16(input, &mut ip)?;
+                let dist = ((t & 8) << 11) + (v >> 2);
+                if dist == 0 {
+                    // End of stream marker
+                    return (len == 3).then_some((ip, op));
+                }
+                (len, dist + 0x4000, v & 3)
+            }
+            32..64 => {
+                let len = read_len(input, &mut ip, t & 31, 31)? + 2;
+                let v = read_u16(input, &mut ip)?;
+                (len, 1 + (v >> 2), v & 3)
+            }
+            _ => {
+                let dist = 1 + ((t >> 2) & 7) + (usize::from(*input.get(ip)?) << 3);
+                ip += 1;
+                ((t >> 5) + 1, dist, t & 3)
+            }
+        };
+
+        if dist > op || len > out.len() - op {
+            return None;
+        }
+        // Byte by byte, overlapping matches repeat their pattern
+        for k in op..op + len {
+            out[k] = out[k - dist];
+        }
+        op += len;
+
+        copy_literals(input, &mut ip, out, &mut op, literals)?;
+        state = literals;
+    }
+}
+
+fn copy_literals(
+    input: &[u8],
+    ip: &mut usize,
+    out: &mut [u8],
+    op: &mut usize,
+    len: usize,
+) -> Option<()> {
+    let src = input.get(*ip..ip.checked_add(len)?)?;
+    out.get_mut(*op..*op + len)?.copy_from_slice(src);
+    *ip += len;
+    *op += len;
+    Some(())
+}
+
+/// Length held by the `bits` of an instruction, zero meaning it continues over the next bytes.
+fn read_len(input: &[u8], ip: &mut usize, bits: usize, max: usize) -> Option<usize> {
+    if bits != 0 {
+        return Some(bits);
+    }
+
+    let mut len = max;
+    loop {
+        let byte = *input.get(*ip)?;
+        *ip += 1;
+        if byte != 0 {
+            return len.checked_add(usize::from(byte));
+        }
+        len = len.checked_add(255)?;
+    }
+}
+
+fn read_u16(input: &[u8], ip: &mut usize) -> Option<usize> {
+    let bytes = input.get(*ip..*ip + 2)?;
+    *ip += 2;
+    Some(usize::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const END: [u8; 3] = [0x11, 0x00, 0x00];
+
+    fn decode(stream: &[u8], size: usize) -> Option<Vec<u8>> {
+        let mut out = vec![0u8; size];
+        let (consumed, len) = decompress(stream, &mut out)?;
+        assert_eq!(consumed, stream.len());
+        out.truncate(len);
+        Some(out)
+    }
+
+    #[test]
+    fn literals_and_matches_are_decoded() {
+        // Leading literals, then a long match
+        let stream = [&[21, b'a', b'b', b'c', b'd', 0x26, 0x0c, 0x00][..], &END].concat();
+        assert_eq!(decode(&stream, 12).unwrap(), b"abcdabcdabcd");
+
+        // Short match followed by two literals
+        let stream = [
+            &[21, b'a', b'b', b'c', b'd', 0x4e, 0x00, b'x', b'y'][..],
+            &END,
+        ]
+        .concat();
+        assert_eq!(decode(&stream, 9).unwrap(), b"abcdabcxy");
+
+        // Literal run with an extended length
+        let literals: Vec<u8> = (0..20).collect();
+        let stream = [&[0x00, 0x02][..], &literals, &END].concat();
+        assert_eq!(decode(&stream, 20).unwrap(), literals);
+
+        // One literal repeated by a match with an extended length
+        let stream = [
+            &[18, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0, 229, 0x00, 0x00][..],
+            &END,
+        ]
+        .concat();
+        assert_eq!(decode(&stream, 0x800).unwrap(), vec![0u8; 0x800]);
+    }
+
+    #[test]
+    fn malformed_streams_are_rejected() {
+        // Match before any output
+        assert_eq!(decode(&[0x26, 0x0c, 0x00], 16), None);
+        // Missing end marker
+        assert_eq!(decode(&[21, b'a', b'b', b'c', b'd'], 16), None);
+        // Output too small
+        assert_eq!(
+            decode(&[&[21, b'a', b'b', b'c', b'd'][..], &END].concat(), 2),
+            None
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub blocks_done: usize,
+    pub total_blocks: usize,
+    pub bytes_in: u64,  // Bytes consumed from the input so far
+    pub bytes_out: u64, // Bytes produced so far
+}
+
+impl Progress {
+    /// Output size relative to the input size, 1.0 until anything was consumed.
+    #[must_use]
+    #[expect(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+
+        self.bytes_out as f64 / self.bytes_in as f64
+    }
+}
+
+/// Receives a `Progress` update after every processed block.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Send + Sync> ProgressObserver for F {
+    fn on_progress(&self, progress: &Progress) {
+        self(progress);
+    }
+}
+
+/// Optional observer shared by the operation options.
+#[derive(Clone, Default)]
+pub(crate) struct Observer(Option<Arc<dyn ProgressObserver>>);
+
+impl Observer {
+    pub(crate) fn new(observer: impl ProgressObserver + 'static) -> Self {
+        Self(Some(Arc::new(observer)))
+    }
+
+    pub(crate) fn report(&self, progress: &Progress) {
+        if let Some(observer) = &self.0 {
+            observer.on_progress(progress);
+        }
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(..)"),
+            None => f.write_str("None"),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use parking_lot::{Condvar, Mutex};
+
+/// Images with fewer blocks are decoded on the calling thread, workers aren't worth spawning
+pub(crate) const PARALLEL_MIN_BLOCKS: usize = 256;
+
+/// Blocking FIFO shared between the threads of a pipeline, closable to tear it down.
+pub(crate) struct BoundedQueue<T> {
+    pub(crate) capacity: usize,
+    inner: Arc<(Mutex<QueueState<T>>, Condvar)>,
+}
+
+// Not derived, values don't need to be `Clone` to share the queue
+impl<T> Clone for BoundedQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> BoundedQueue<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let state = QueueState {
+            items: VecDeque::new(),
+            closed: false,
+        };
+
+        Self {
+            capacity,
+            inner: Arc::new((Mutex::new(state), Condvar::new())),
+        }
+    }
+
+    /// Returns `false` if the queue was closed, dropping `val`.
+    pub(crate) fn push(&self, val: T) -> bool {
+        let (lock, cvar) = &*self.inner;
+        let mut state = lock.lock();
+        while state.items.len() >= self.capacity && !state.closed {
+            cvar.wait(&mut state);
+        }
+        if state.closed {
+            return false;
+        }
+        state.items.push_back(val);
+        // Producers and consumers share the condvar, wake them all to not lose a wakeup
+        cvar.notify_all();
+        true
+    }
+
+    /// Returns `None` once the queue is closed, even if values are left.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let (lock, cvar) = &*self.inner;
+        let mut state = lock.lock();
+        while state.items.is_empty() && !state.closed {
+            cvar.wait(&mut state);
+        }
+        if state.closed {
+            return None;
+        }
+        let value = state.items.pop_front();
+        cvar.notify_all();
+        value
+    }
+
+    /// Wakes up and turns away every current and future caller.
+    pub(crate) fn close(&self) {
+        let (lock, cvar) = &*self.inner;
+        lock.lock().closed = true;
+        cvar.notify_all();
+    }
+}
@@ -0,0 +1,162 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::block::decode_block;
+use crate::ciso_header::CisoHeader;
+use crate::error::CisoError;
+use crate::index::CisoIndex;
+
+/// Random access over the decompressed content of a CSO image.
+///
+/// The header and index are parsed once, then each read only inflates the blocks it touches.
+pub struct CisoReader<R> {
+    inner: R,
+    header: CisoHeader,
+    index: CisoIndex,
+    in_buf: Vec<u8>,
+    cursor: BlockCursor,
+}
+
+impl<R: Read + Seek> CisoReader<R> {
+    pub fn new(mut inner: R) -> Result<Self, CisoError> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+
+        let header = CisoHeader::read_from(&mut inner)?;
+
+        let index = CisoIndex::read_from(&mut inner, &header, file_len)?;
+        let block_size = header.block_size as usize;
+
+        Ok(Self {
+            inner,
+            cursor: BlockCursor::new(header.total_bytes, header.block_size),
+            header,
+            index,
+            in_buf: vec![0u8; block_size * 2],
+        })
+    }
+
+    #[must_use]
+    pub fn header(&self) -> &CisoHeader {
+        &self.header
+    }
+
+    /// Block index, to inspect how the image is laid out in the file.
+    #[must_use]
+    pub fn index(&self) -> &CisoIndex {
+        &self.index
+    }
+
+    /// Size of the decompressed image.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.header.total_bytes
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.header.total_bytes == 0
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for CisoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (inner, index, in_buf) = (&mut self.inner, &self.index, &mut self.in_buf);
+        self.cursor.read(buf, |i, block| {
+            decode_block(index, i, in_buf, block, |off, buf| {
+                inner.seek(SeekFrom::Start(off))?;
+                inner.read_exact(buf)
+            })?;
+            Ok(())
+        })
+    }
+}
+
+impl<R: Read + Seek> Seek for CisoReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+/// Position within a decompressed image and its last decoded block, shared by the readers of
+/// every format.
+pub(crate) struct BlockCursor {
+    total_bytes: u64,
+    block_size: u64,
+    pos: u64,
+    block: Vec<u8>,
+    current: Option<usize>, // Block currently held in `block`
+}
+
+impl BlockCursor {
+    pub(crate) fn new(total_bytes: u64, block_size: u32) -> Self {
+        Self {
+            total_bytes,
+            block_size: u64::from(block_size),
+            pos: 0,
+            block: Vec::with_capacity(block_size as usize),
+            current: None,
+        }
+    }
+
+    /// Block `i`, decoded by `load` into the given buffer unless it is the one already held.
+    pub(crate) fn load(
+        &mut self,
+        i: usize,
+        load: impl FnOnce(usize, &mut Vec<u8>) -> Result<(), CisoError>,
+    ) -> Result<&[u8], CisoError> {
+        if self.current != Some(i) {
+            self.current = None;
+            load(i, &mut self.block)?;
+            self.current = Some(i);
+        }
+
+        Ok(&self.block)
+    }
+
+    /// Reads from the current position, loading the blocks it reaches through `load`.
+    #[expect(clippy::cast_possible_truncation)]
+    pub(crate) fn read(
+        &mut self,
+        buf: &mut [u8],
+        mut load: impl FnMut(usize, &mut Vec<u8>) -> Result<(), CisoError>,
+    ) -> io::Result<usize> {
+        let mut done = 0;
+
+        while done < buf.len() && self.pos < self.total_bytes {
+            let i = (self.pos / self.block_size) as usize;
+            let in_block = (self.pos % self.block_size) as usize;
+
+            let block = self.load(i, &mut load)?;
+
+            let n = (block.len() - in_block).min(buf.len() - done);
+            buf[done..done + n].copy_from_slice(&block[in_block..in_block + n]);
+
+            done += n;
+            self.pos += n as u64;
+        }
+
+        Ok(done)
+    }
+
+    pub(crate) fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.total_bytes.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+
+        let Some(new_pos) = new_pos else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
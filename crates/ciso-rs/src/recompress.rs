@@ -0,0 +1,163 @@
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::block::decode_block;
+use crate::ciso_header::{CisoFormat, CisoHeader};
+use crate::compress::{
+    BlockEncoder, CompressOptions, compress_ciso_stream, finish_output, output_index, store_block,
+    write_placeholder,
+};
+use crate::error::CisoError;
+use crate::index::{BlockCodec, CisoIndex};
+use crate::progress::Progress;
+use crate::reader::CisoReader;
+
+#[derive(Debug, Clone, Default)]
+pub struct RecompressOptions {
+    compress: CompressOptions,
+    reencode_all: bool,
+}
+
+impl RecompressOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Layout and level of the output, with the progress observer and cancellation token.
+    ///
+    /// Blocks are recompressed in order on the calling thread: `threads` and `queue_depth`
+    /// are ignored.
+    #[must_use]
+    pub fn compress(mut self, options: CompressOptions) -> Self {
+        self.compress = options;
+        self
+    }
+
+    /// Encodes every block again instead of copying as stored the blocks the output can hold.
+    ///
+    /// Implied by a level set on the compress options, which then applies to every block.
+    #[must_use]
+    pub fn reencode_all(mut self, reencode_all: bool) -> Self {
+        self.reencode_all = reencode_all;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), CisoError> {
+        self.compress.validate()
+    }
+}
+
+#[derive(Debug)]
+pub struct RecompressReport {
+    pub total_blocks: usize,
+    pub copied_blocks: usize, // Stored as in the input, without being encoded again
+}
+
+/// Converts a CSO, CSO v2 or ZSO image into another one, without going through a plain image.
+///
+/// Every block is decoded, so that damaged inputs are reported instead of copied. Blocks whose
+/// codec the output format holds are then copied as stored when the block size is unchanged,
+/// the others being compressed again. Plain blocks are only copied between images of the same
+/// format, another codec may compress them. An explicit level re-encodes every block.
+pub fn recompress_ciso(
+    mut input: impl Read + Seek,
+    output: impl Write + Seek,
+    options: &RecompressOptions,
+) -> Result<RecompressReport, CisoError> {
+    options.validate()?;
+    let compress = &options.compress;
+
+    let file_len = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    let header = CisoHeader::read_from(&mut input)?;
+    let src = CisoIndex::read_from(&mut input, &header, file_len)?;
+    let total_blocks = src.len();
+
+    // Blocks of another size are cut from the decoded image
+    if compress.block_size != header.block_size {
+        input.seek(SeekFrom::Start(0))?;
+        compress_ciso_stream(CisoReader::new(input)?, output, compress)?;
+        return Ok(RecompressReport {
+            total_blocks,
+            copied_blocks: 0,
+        });
+    }
+
+    let mut index = output_index(header.total_bytes, compress)?;
+
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    let mut write_pos = write_placeholder(&mut writer, &index)?;
+
+    let mut encoder = BlockEncoder::new(index.header(), compress.effective_level());
+    let mut stored = Vec::with_capacity(header.block_size as usize * 2);
+    let mut data = Vec::with_capacity(header.block_size as usize);
+    let mut copied_blocks = 0;
+    let reencode_all = options.reencode_all || compress.level.is_some();
+
+    for i in 0..total_blocks {
+        compress.cancel.check()?;
+
+        let stream_len = decode_block(&src, i, &mut stored, &mut data, |offset, buf| {
+            input.seek(SeekFrom::Start(offset))?;
+            input.read_exact(buf)
+        })?;
+
+        // The stream only, without the alignment padding of the input
+        let codec = src.codec(i);
+        let stored = if codec == BlockCodec::Plain {
+            &data
+        } else {
+            &stored[..stream_len]
+        };
+
+        let (codec, compressed) = if !reencode_all
+            && copyable(&header, index.header(), codec, stored.len(), data.len())
+        {
+            copied_blocks += 1;
+            (codec, stored.to_vec())
+        } else {
+            encoder.encode(&data, i)?
+        };
+        write_pos = store_block(
+            &mut writer,
+            &mut index,
+            write_pos,
+            &data,
+            codec,
+            &compressed,
+        )?;
+
+        compress.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in: src.block_range(i).end,
+            bytes_out: write_pos,
+        });
+    }
+
+    finish_output(&mut writer, index, write_pos)?;
+
+    Ok(RecompressReport {
+        total_blocks,
+        copied_blocks,
+    })
+}
+
+/// Whether a block stored with `codec` on `stored_len` bytes is valid as is in the output.
+fn copyable(
+    src: &CisoHeader,
+    dst: &CisoHeader,
+    codec: BlockCodec,
+    stored_len: usize,
+    len: usize,
+) -> bool {
+    match (codec, dst.format()) {
+        (BlockCodec::Plain, format) => format == src.format(),
+        // CSO v2 readers take the blocks reaching their decompressed size for plain ones
+        (BlockCodec::Deflate | BlockCodec::Lz4, CisoFormat::Cso2) => {
+            stored_len.next_multiple_of(1 << dst.align) < len
+        }
+        (BlockCodec::Deflate, CisoFormat::Cso) | (BlockCodec::Lz4, CisoFormat::Zso) => true,
+        _ => false,
+    }
+}
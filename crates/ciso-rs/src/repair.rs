@@ -0,0 +1,424 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+
+use flate2::{Decompress, FlushDecompress, Status};
+use memmap2::Mmap;
+
+use crate::block::decode_block;
+use crate::cancel::CancellationToken;
+use crate::ciso_header::{CisoFormat, CisoHeader};
+use crate::compress::{BlockEncoder, finish_output, store_block, write_placeholder};
+use crate::error::CisoError;
+use crate::image::SECTOR_SIZE;
+use crate::index::CisoIndex;
+use crate::lz4;
+use crate::progress::{Observer, Progress, ProgressObserver};
+
+#[derive(Debug, Clone)]
+pub struct RepairOptions {
+    level: u32,
+    reference: Option<Arc<File>>, // Image providing the data of lost blocks, zeros otherwise
+    progress: Observer,
+    cancel: CancellationToken,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            reference: None,
+            progress: Observer::default(),
+            cancel: CancellationToken::default(),
+        }
+    }
+}
+
+impl RepairOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compression level of the repaired CSO, from 0 (store) to 9 (best).
+    ///
+    /// ZSO images keep their format and use it as the LZ4 HC level, up to 12.
+    #[must_use]
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Plain image, e.g. another dump of the same disc, whose data replaces the lost blocks.
+    ///
+    /// Lost blocks are zero-filled when unset, or when the reference is too short.
+    #[must_use]
+    pub fn reference(mut self, reference: File) -> Self {
+        self.reference = Some(Arc::new(reference));
+        self
+    }
+
+    /// Observer notified each time a block is written to the output.
+    #[must_use]
+    pub fn progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Observer::new(observer);
+        self
+    }
+
+    /// Token making the repair stop with `CisoError::Cancelled` once cancelled.
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Checks the level against the largest one of any format, `repair_ciso` then checks it
+    /// against the format of its input.
+    pub fn validate(&self) -> Result<(), CisoError> {
+        self.validate_level(CisoFormat::Zso)
+    }
+
+    fn validate_level(&self, format: CisoFormat) -> Result<(), CisoError> {
+        let max_level = match format {
+            CisoFormat::Cso | CisoFormat::Cso2 => 9,
+            CisoFormat::Zso => 12,
+        };
+        if self.level > max_level {
+            return Err(CisoError::InvalidOption {
+                name: "level",
+                value: u64::from(self.level),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RepairReport {
+    pub total_blocks: usize,
+    pub index_rebuilt: bool, // The index was unusable, blocks were located by scanning the data
+    pub lost_blocks: Vec<usize>,
+    pub lost_sectors: Vec<Range<u64>>, // Merged LBA ranges of the lost blocks
+}
+
+impl RepairReport {
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.lost_blocks.is_empty()
+    }
+}
+
+/// Writes a valid CSO out of a damaged one, replacing the blocks which can't be recovered.
+///
+/// The output keeps the format of the input. The header must be readable, the index is rebuilt
+/// by scanning the data for the deflate or LZ4 streams of its format when it is inconsistent.
+#[expect(clippy::cast_possible_truncation)]
+pub fn repair_ciso(
+    mut input: File,
+    output: File,
+    options: &RepairOptions,
+) -> Result<RepairReport, CisoError> {
+    options.validate()?;
+
+    input.seek(SeekFrom::Start(0))?;
+    let header = CisoHeader::read_from(&mut input)?;
+    options.validate_level(header.format())?;
+
+    let file_len = input.metadata()?.len();
+    let index = CisoIndex::read_from(&mut input, &header, file_len)?;
+    let total_blocks = index.len();
+
+    let mmap = unsafe { Mmap::map(&input)? };
+    let mut salvager = Salvager::new(&index, &mmap);
+    let index_rebuilt = salvager.scan.is_some();
+
+    let block_size = header.block_size as usize;
+    let align = header
+        .align
+        .max(CisoHeader::min_align(header.total_bytes, header.block_size));
+    let out_header = CisoHeader::with_format(
+        header.total_bytes,
+        header.block_size,
+        align,
+        header.format(),
+    );
+    out_header.validate()?;
+
+    let mut out_index = CisoIndex::new(out_header);
+    let mut writer = BufWriter::with_capacity(1 << 20, output); // 1MiB
+    let mut write_pos = write_placeholder(&mut writer, &out_index)?;
+
+    let mut encoder = BlockEncoder::new(&out_header, options.level);
+    let mut data = Vec::with_capacity(block_size);
+    let mut lost_blocks = Vec::new();
+
+    for i in 0..total_blocks {
+        options.cancel.check()?;
+
+        if salvager.block(i, &mut data).is_err() {
+            lost_blocks.push(i);
+            fill_lost_block(&index, i, options.reference.as_deref(), &mut data)?;
+        }
+
+        let (codec, compressed) = encoder.encode(&data, i)?;
+        write_pos = store_block(
+            &mut writer,
+            &mut out_index,
+            write_pos,
+            &data,
+            codec,
+            &compressed,
+        )?;
+
+        options.progress.report(&Progress {
+            blocks_done: i + 1,
+            total_blocks,
+            bytes_in: ((i + 1) * block_size).min(header.total_bytes as usize) as u64,
+            bytes_out: write_pos,
+        });
+    }
+
+    finish_output(&mut writer, out_index, write_pos)?;
+
+    let lost_sectors = lost_sectors(&index, &lost_blocks);
+
+    Ok(RepairReport {
+        total_blocks,
+        index_rebuilt,
+        lost_blocks,
+        lost_sectors,
+    })
+}
+
+/// Replaces the data of block `i` with the reference image, or zeros.
+#[expect(clippy::cast_possible_truncation)]
+fn fill_lost_block(
+    index: &CisoIndex,
+    i: usize,
+    reference: Option<&File>,
+    data: &mut Vec<u8>,
+) -> Result<(), CisoError> {
+    let range = index.block_range(i);
+    data.clear();
+    data.resize((range.end - range.start) as usize, 0);
+
+    if let Some(mut reference) = reference {
+        reference.seek(SeekFrom::Start(range.start))?;
+        // A short reference leaves the rest of the block zero-filled
+        let mut filled = 0;
+        while filled < data.len() {
+            match reference.read(&mut data[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the data of every block, through the index or by scanning the file when it is unusable.
+struct Salvager<'a> {
+    index: &'a CisoIndex,
+    data: &'a [u8],
+    scan: Option<u64>, // Position of the next block once rebuilding the index
+    in_buf: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl<'a> Salvager<'a> {
+    fn new(index: &'a CisoIndex, data: &'a [u8]) -> Self {
+        let data_start = index.data_start();
+        let offsets = (0..=index.len()).map(|i| index.offset(i));
+
+        // Monotonic entries within the file are trusted, bad blocks will fail on their own
+        let sane = offsets.clone().all(|off| off >= data_start)
+            && offsets.clone().zip(offsets.skip(1)).all(|(a, b)| a <= b)
+            && index.offset(index.len()) <= data.len() as u64;
+
+        Self {
+            index,
+            data,
+            scan: (!sane).then_some(data_start),
+            in_buf: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn block(&mut self, i: usize, out: &mut Vec<u8>) -> Result<(), CisoError> {
+        let Some(pos) = self.scan else {
+            let data = self.data;
+            decode_block(self.index, i, &mut self.in_buf, out, |off, buf| {
+                let stored = usize::try_from(off)
+                    .ok()
+                    .and_then(|start| data.get(start..start + buf.len()))
+                    .ok_or(io::ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(stored);
+                Ok(())
+            })?;
+            return Ok(());
+        };
+
+        let expected = self.expected_size(i);
+        let align = self.index.header().align;
+
+        if let Some(consumed) = decode_at(self.index, self.data, pos, expected, out) {
+            self.scan = Some(align_up(pos + consumed as u64, align));
+            return Ok(());
+        }
+
+        if self.looks_plain(i, pos) {
+            let start = pos as usize;
+            out.clear();
+            out.extend_from_slice(&self.data[start..start + expected]);
+            self.scan = Some(align_up(pos + expected as u64, align));
+            return Ok(());
+        }
+
+        self.scan = Some(self.resync(i + 1, pos));
+        Err(CisoError::CorruptBlock {
+            block: i,
+            offset: pos,
+        })
+    }
+
+    /// Whether block `i` can be a plain block stored at `pos`.
+    fn looks_plain(&mut self, i: usize, pos: u64) -> bool {
+        let end = pos + self.expected_size(i) as u64;
+        if end > self.data.len() as u64 {
+            return false;
+        }
+
+        // Entries agreeing with the scan are likely intact
+        if self.index.offset(i) == pos {
+            return self.index.is_plain(i);
+        }
+
+        // Otherwise the next block must start right after it
+        let next = i + 1;
+        next == self.index.len() || {
+            let next_pos = align_up(end, self.index.header().align);
+            let expected = self.expected_size(next);
+            decode_at(self.index, self.data, next_pos, expected, &mut self.scratch).is_some()
+        }
+    }
+
+    /// Finds where block `next` starts after an unreadable block stored at `pos`.
+    fn resync(&mut self, next: usize, pos: u64) -> u64 {
+        let header = self.index.header();
+        let unit = 1u64 << header.align;
+        if next >= self.index.len() {
+            return pos + unit;
+        }
+
+        // A compressed block is always smaller than its plain size
+        let limit = pos + u64::from(header.block_size) + unit;
+        let hinted = self.index.offset(next);
+        if hinted > pos && hinted <= limit {
+            return hinted;
+        }
+
+        let expected = self.expected_size(next);
+        let mut candidate = pos + unit;
+        while candidate <= limit {
+            if decode_at(
+                self.index,
+                self.data,
+                candidate,
+                expected,
+                &mut self.scratch,
+            )
+            .is_some()
+            {
+                return candidate;
+            }
+            candidate += unit;
+        }
+
+        // Assume a plain block was lost
+        align_up(pos + self.expected_size(next - 1) as u64, header.align)
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn expected_size(&self, i: usize) -> usize {
+        let range = self.index.block_range(i);
+        (range.end - range.start) as usize
+    }
+}
+
+/// Decodes a compressed block starting at `pos` with the codecs of the index's format, returns
+/// its size if it yields exactly `expected` bytes.
+fn decode_at(
+    index: &CisoIndex,
+    data: &[u8],
+    pos: u64,
+    expected: usize,
+    out: &mut Vec<u8>,
+) -> Option<usize> {
+    match index.header().format() {
+        CisoFormat::Cso => inflate_at(data, pos, expected, out),
+        CisoFormat::Cso2 => {
+            inflate_at(data, pos, expected, out).or_else(|| lz4_at(data, pos, expected, out))
+        }
+        CisoFormat::Zso => lz4_at(data, pos, expected, out),
+    }
+}
+
+/// Inflates a raw deflate stream starting at `pos`, returns its size if it yields exactly `expected` bytes.
+#[expect(clippy::cast_possible_truncation)]
+fn inflate_at(data: &[u8], pos: u64, expected: usize, out: &mut Vec<u8>) -> Option<usize> {
+    let start = usize::try_from(pos).ok()?;
+    // Compressed blocks are smaller than their plain size, plus some slack for the stream framing
+    let end = (start + expected + 64).min(data.len());
+    let input = data.get(start..end)?;
+
+    out.clear();
+    out.resize(expected, 0);
+
+    let mut decomp = Decompress::new(false);
+    let status = decomp
+        .decompress(input, out, FlushDecompress::Finish)
+        .ok()?;
+
+    (status == Status::StreamEnd && decomp.total_out() as usize == expected)
+        .then_some(decomp.total_in() as usize)
+}
+
+/// Decodes a raw LZ4 block starting at `pos`, returns its size if it yields exactly `expected` bytes.
+fn lz4_at(data: &[u8], pos: u64, expected: usize, out: &mut Vec<u8>) -> Option<usize> {
+    let start = usize::try_from(pos).ok()?;
+    // Same bound as for deflate streams, LZ4 blocks larger than their plain size are stored plain
+    let end = (start + expected + 64).min(data.len());
+    let input = data.get(start..end)?;
+
+    out.clear();
+    out.resize(expected, 0);
+
+    let (consumed, len) = lz4::decompress(input, out)?;
+    (len == expected).then_some(consumed)
+}
+
+fn align_up(pos: impl Into<u64>, align: u8) -> u64 {
+    pos.into().next_multiple_of(1 << align)
+}
+
+/// LBA ranges covered by `blocks`, contiguous ranges being merged.
+fn lost_sectors(index: &CisoIndex, blocks: &[usize]) -> Vec<Range<u64>> {
+    let sector_size = SECTOR_SIZE as u64;
+    let mut sectors: Vec<Range<u64>> = Vec::new();
+
+    for &block in blocks {
+        let range = index.block_range(block);
+        let lbas = range.start / sector_size..range.end.div_ceil(sector_size);
+
+        match sectors.last_mut() {
+            Some(last) if last.end >= lbas.start => last.end = lbas.end,
+            _ => sectors.push(lbas),
+        }
+    }
+
+    sectors
+}
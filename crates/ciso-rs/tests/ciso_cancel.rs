@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+use ciso_rs::{
+    CancellationToken, CheckOptions, CisoError, CompressOptions, DecompressOptions, Progress,
+    check_ciso_with, compress_ciso, compress_ciso_with, decompress_ciso_with,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 2000 * BLOCK_SIZE;
+
+#[test]
+fn ciso_cancel_stops_every_operation() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    let out_path = tmp.path().join("output.iso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+
+    // Cancel halfway through, with workers and producer still running
+    let token = CancellationToken::new();
+    let options = CompressOptions::new()
+        .threads(4)
+        .queue_depth(2)
+        .cancellation(token.clone())
+        .progress({
+            let token = token.clone();
+            move |p: &Progress| {
+                if p.blocks_done == p.total_blocks / 2 {
+                    token.cancel();
+                }
+            }
+        });
+    let err = compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options);
+    assert!(matches!(err, Err(CisoError::Cancelled)));
+
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let options = DecompressOptions::new().cancellation(token.clone());
+    let err = decompress_ciso_with(File::open(&cso_path)?, File::create(&out_path)?, &options);
+    assert!(matches!(err, Err(CisoError::Cancelled)));
+
+    let options = CheckOptions::new().full(true).cancellation(token);
+    let err = check_ciso_with(File::open(&cso_path)?, &options);
+    assert!(matches!(err, Err(CisoError::Cancelled)));
+
+    Ok(())
+}
+
+/// Reader cancelled from the start, counting the reads it is asked for.
+struct Cancelled(usize);
+
+impl Read for Cancelled {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        self.0 += 1;
+        Err(CisoError::Cancelled.into())
+    }
+}
+
+#[test]
+fn ciso_cancel_through_io_errors_is_not_retried() {
+    let mut reader = Cancelled(0);
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(reader.0, 1);
+    assert!(matches!(CisoError::from(err), CisoError::Cancelled));
+}
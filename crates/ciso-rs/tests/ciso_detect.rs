@@ -0,0 +1,62 @@
+use std::io::{Cursor, Seek};
+
+use ciso_rs::{
+    CisoError, CisoFormat, CompressOptions, ImageFormat, LegacyFormat, compress_ciso_stream,
+    detect_format,
+};
+
+/// Image with an ISO9660 primary volume descriptor in sector 16.
+fn make_iso() -> Vec<u8> {
+    let mut iso = vec![0u8; 20 * 0x800];
+    iso[0x8000..0x8006].copy_from_slice(b"\x01CD001");
+    iso
+}
+
+#[test]
+fn formats_are_detected_by_content() -> Result<(), CisoError> {
+    let iso = make_iso();
+    assert_eq!(detect_format(Cursor::new(&iso))?, Some(ImageFormat::Iso));
+
+    for format in [CisoFormat::Cso, CisoFormat::Cso2, CisoFormat::Zso] {
+        let mut cso = Cursor::new(Vec::new());
+        let options = CompressOptions::new().format(format);
+        compress_ciso_stream(Cursor::new(&iso), &mut cso, &options)?;
+        assert_eq!(detect_format(&mut cso)?, Some(ImageFormat::Ciso(format)));
+        // Left at the start, ready to be read
+        assert_eq!(cso.stream_position()?, 0);
+    }
+
+    let mut dax = b"DAX\0".to_vec();
+    dax.resize(0x20, 0);
+    assert_eq!(
+        detect_format(Cursor::new(&dax))?,
+        Some(ImageFormat::Legacy(LegacyFormat::Dax))
+    );
+
+    let mut jso = b"JISO\x03\x01".to_vec();
+    jso.resize(0x30, 0);
+    assert_eq!(
+        detect_format(Cursor::new(&jso))?,
+        Some(ImageFormat::Legacy(LegacyFormat::Jso))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn unknown_content_is_not_guessed() -> Result<(), CisoError> {
+    assert_eq!(detect_format(Cursor::new(&[]))?, None);
+    assert_eq!(detect_format(Cursor::new(b"CIS"))?, None);
+
+    // Raw image without a filesystem, or with the descriptor cut short
+    assert_eq!(detect_format(Cursor::new(vec![0u8; 0x10000]))?, None);
+    assert_eq!(detect_format(Cursor::new(&make_iso()[..0x8004]))?, None);
+
+    // A truncated header still tells its magic apart
+    assert_eq!(
+        detect_format(Cursor::new(b"ZISO\x18\0"))?,
+        Some(ImageFormat::Ciso(CisoFormat::Zso))
+    );
+
+    Ok(())
+}
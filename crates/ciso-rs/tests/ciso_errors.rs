@@ -0,0 +1,296 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use ciso_rs::{
+    CheckOptions, CisoError, CisoHeader, CisoImage, CisoReader, check_ciso, check_ciso_report,
+    check_ciso_with, compress_ciso, decompress_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 64 * BLOCK_SIZE;
+const INDEX_POS: usize = 0x18;
+
+fn make_cso(dir: &Path) -> io::Result<Vec<u8>> {
+    let iso_path = dir.join("input.iso");
+    let cso_path = dir.join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    fs::read(&cso_path)
+}
+
+fn check_bytes(dir: &Path, cso: &[u8], full: bool) -> Result<(), CisoError> {
+    let path = dir.join("corrupt.cso");
+    fs::write(&path, cso)?;
+    check_ciso(File::open(&path)?, full)
+}
+
+fn index_entry(cso: &[u8], i: usize) -> u32 {
+    let pos = INDEX_POS + i * 4;
+    u32::from_le_bytes(cso[pos..pos + 4].try_into().unwrap())
+}
+
+fn set_index_entry(cso: &mut [u8], i: usize, v: u32) {
+    let pos = INDEX_POS + i * 4;
+    cso[pos..pos + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+#[test]
+fn ciso_errors_are_typed() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let cso = make_cso(tmp.path())?;
+
+    check_bytes(tmp.path(), &cso, true)?;
+
+    let mut bad_magic = cso.clone();
+    bad_magic[..4].copy_from_slice(b"NOPE");
+    assert!(matches!(
+        check_bytes(tmp.path(), &bad_magic, false),
+        Err(CisoError::BadMagic { magic }) if magic == *b"NOPE"
+    ));
+
+    // Block 2 pointing back to block 0
+    let mut non_monotonic = cso.clone();
+    set_index_entry(&mut non_monotonic, 2, index_entry(&cso, 0));
+    assert!(matches!(
+        check_bytes(tmp.path(), &non_monotonic, false),
+        Err(CisoError::NonMonotonicIndex { block: 2, .. })
+    ));
+
+    let mut corrupt = cso.clone();
+    let off = index_entry(&cso, 0) as usize;
+    corrupt[off..off + 4].fill(0xff);
+    assert!(matches!(
+        check_bytes(tmp.path(), &corrupt, true),
+        Err(CisoError::CorruptBlock { block: 0, offset }) if offset == off as u64
+    ));
+
+    // Errors surfaced through `Read` keep their type once converted back
+    let path = tmp.path().join("corrupt.cso");
+    let mut reader = CisoReader::new(File::open(&path)?).map_err(io::Error::from)?;
+    let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        CisoError::from(err),
+        CisoError::CorruptBlock { block: 0, .. }
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn ciso_parallel_check_reports_first_error() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, 1024 * BLOCK_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    let cso = fs::read(&cso_path)?;
+
+    let check = |cso: &[u8], threads| {
+        fs::write(&cso_path, cso)?;
+        let options = CheckOptions::new().full(true).threads(threads);
+        check_ciso_with(File::open(&cso_path)?, &options)
+    };
+
+    // Zero filled blocks are always compressed
+    let corrupt_block = |cso: &mut [u8], i| {
+        let off = (index_entry(cso, i) & 0x7fff_ffff) as usize;
+        cso[off..off + 4].fill(0xff);
+    };
+
+    for threads in [1, 3] {
+        check(&cso, threads)?;
+
+        // Structural error at block 600, corrupt zlib streams before and after it
+        let mut corrupt = cso.clone();
+        set_index_entry(&mut corrupt, 600, index_entry(&cso, 0));
+        corrupt_block(&mut corrupt, 900);
+        assert!(matches!(
+            check(&corrupt, threads),
+            Err(CisoError::NonMonotonicIndex { block: 600, .. })
+        ));
+
+        corrupt_block(&mut corrupt, 300);
+        assert!(matches!(
+            check(&corrupt, threads),
+            Err(CisoError::CorruptBlock { block: 300, .. })
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_check_report_lists_every_faulty_block() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, 1024 * BLOCK_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    let cso = fs::read(&cso_path)?;
+
+    let report = |cso: &[u8], threads| {
+        fs::write(&cso_path, cso)?;
+        let options = CheckOptions::new().full(true).threads(threads);
+        check_ciso_report(File::open(&cso_path)?, &options)
+    };
+
+    for threads in [1, 3] {
+        let healthy = report(&cso, threads)?;
+        assert!(healthy.is_ok());
+        assert_eq!(healthy.total_blocks, 1024);
+        assert_eq!(healthy.plain_blocks + healthy.compressed_blocks, 1024);
+        assert!(healthy.plain_blocks > 0 && healthy.compressed_blocks > 0);
+        assert_eq!(healthy.trailing_bytes, 0);
+        assert!(healthy.ratio() < 1.0);
+
+        let mut corrupt = cso.clone();
+        for block in [300, 900] {
+            let off = (index_entry(&cso, block) & 0x7fff_ffff) as usize;
+            corrupt[off..off + 4].fill(0xff);
+        }
+        // Block 599 can't be sized anymore, and block 600 goes backwards
+        set_index_entry(&mut corrupt, 600, index_entry(&cso, 0));
+        corrupt.extend_from_slice(b"garbage");
+
+        let damaged = report(&corrupt, threads)?;
+        assert_eq!(damaged.trailing_bytes, 7);
+
+        let blocks = damaged
+            .faulty_blocks
+            .iter()
+            .map(|faulty| faulty.block)
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [300, 599, 600, 900]);
+
+        let first = &damaged.faulty_blocks[0];
+        assert_eq!(first.range, 300 * 2048..301 * 2048);
+        assert!(matches!(
+            first.error,
+            CisoError::CorruptBlock { block: 300, .. }
+        ));
+        assert!(matches!(
+            damaged.faulty_blocks[2].error,
+            CisoError::NonMonotonicIndex { block: 600, .. }
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_header_is_little_endian_and_validated() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let cso = make_cso(tmp.path())?;
+
+    let header = CisoHeader::with_layout(0x0102_0304_0506, 0x800, 2);
+    let bytes = header.to_bytes();
+    assert_eq!(&bytes[..4], b"CISO");
+    assert_eq!(bytes[0x04..0x08], [0x18, 0, 0, 0]);
+    assert_eq!(
+        bytes[0x08..0x10],
+        [0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0, 0]
+    );
+    assert_eq!(bytes[0x10..0x14], [0, 0x08, 0, 0]);
+    assert_eq!(bytes[0x14..], [1, 2, 0, 0]);
+    assert_eq!(CisoHeader::from_bytes(&bytes), header);
+    assert_eq!(
+        CisoHeader::read_from(&mut &cso[..]).unwrap().block_size,
+        0x800
+    );
+
+    let with_header = |edit: fn(&mut CisoHeader)| {
+        let mut header = CisoHeader::from_bytes(cso[..0x18].try_into().unwrap());
+        edit(&mut header);
+        let mut cso = cso.clone();
+        cso[..0x18].copy_from_slice(&header.to_bytes());
+        cso
+    };
+
+    let bad_size = with_header(|h| h.header_size = 0x20);
+    assert!(matches!(
+        check_bytes(tmp.path(), &bad_size, false),
+        Err(CisoError::BadHeaderSize { size: 0x20 })
+    ));
+
+    let bad_version = with_header(|h| h.ver = 3);
+    assert!(matches!(
+        check_bytes(tmp.path(), &bad_version, false),
+        Err(CisoError::UnsupportedVersion { version: 3 })
+    ));
+
+    // Used to make the block count computation divide by zero
+    for block_size in [0, 3000] {
+        let mut bad_block_size = cso.clone();
+        bad_block_size[0x10..0x14].copy_from_slice(&u32::to_le_bytes(block_size));
+        assert!(matches!(
+            check_bytes(tmp.path(), &bad_block_size, true),
+            Err(CisoError::InvalidBlockSize { block_size: b }) if b == block_size
+        ));
+    }
+
+    let bad_align = with_header(|h| h.align = 40);
+    let path = tmp.path().join("corrupt.cso");
+    fs::write(&path, &bad_align)?;
+    assert!(matches!(
+        decompress_ciso(
+            File::open(&path)?,
+            File::create(tmp.path().join("out.iso"))?
+        ),
+        Err(CisoError::InvalidAlign { align: 40 })
+    ));
+    assert!(matches!(
+        CisoReader::new(File::open(&path)?),
+        Err(CisoError::InvalidAlign { align: 40 })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn ciso_hostile_sizes_are_rejected_before_allocating() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let cso = make_cso(tmp.path())?;
+    let path = tmp.path().join("corrupt.cso");
+
+    // Would need an index of 2^53 entries
+    let mut huge = cso.clone();
+    huge[0x08..0x10].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&path, &huge)?;
+
+    let is_truncated = |res: Result<(), CisoError>| matches!(res, Err(CisoError::TruncatedIndex { file_len, .. }) if file_len == huge.len() as u64);
+    assert!(is_truncated(check_ciso(File::open(&path)?, false)));
+    assert!(is_truncated(decompress_ciso(
+        File::open(&path)?,
+        File::create(tmp.path().join("out.iso"))?,
+    )));
+    assert!(is_truncated(CisoImage::open(File::open(&path)?).map(drop)));
+    assert!(is_truncated(CisoReader::new(Cursor::new(&huge)).map(drop)));
+
+    // Blocks beyond the supported sizes would make every reader allocate them
+    let mut huge_blocks = cso.clone();
+    huge_blocks[0x10..0x14].copy_from_slice(&(1u32 << 30).to_le_bytes());
+    assert!(matches!(
+        CisoReader::new(Cursor::new(&huge_blocks)),
+        Err(CisoError::InvalidBlockSize { .. })
+    ));
+
+    // The end of the data pointing as far as the index can reach, with the largest shift
+    let mut far = cso.clone();
+    far[0x15] = 31;
+    set_index_entry(&mut far, 64, 0x7fff_ffff);
+    let mut reader = CisoReader::new(Cursor::new(&far)).map_err(io::Error::from)?;
+    assert!(io::copy(&mut reader, &mut io::sink()).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use std::fs::{self, File};
+
+use ciso_rs::{BlockEntry, CisoError, CisoHeader, CisoIndex, CisoReader, compress_ciso};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 64 * BLOCK_SIZE + 100;
+
+#[test]
+fn ciso_index_describes_the_layout() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    let cso = fs::read(&cso_path)?;
+
+    let mut bytes = &cso[..];
+    let header = CisoHeader::read_from(&mut bytes)?;
+    let index = CisoIndex::read_from(&mut bytes, &header, cso.len() as u64)?;
+    index.validate(cso.len() as u64)?;
+
+    assert_eq!(index.len(), 65);
+    assert_eq!(index.data_start(), 0x18 + 66 * 4);
+    assert_eq!(index.offset(index.len()), cso.len() as u64);
+
+    // Random blocks are stored plain, the others compressed, up to the short last block
+    let entries = index.iter().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 65);
+    assert_eq!(entries[0].offset, index.data_start());
+    for (i, entry) in entries.iter().enumerate() {
+        if i < 64 {
+            assert_eq!(entry.is_plain, i % 3 == 2, "block {i}");
+        }
+        assert_eq!(entry.offset + entry.stored_len, index.offset(i + 1));
+    }
+    assert_eq!(
+        index.entry(2),
+        Some(BlockEntry {
+            offset: index.offset(2),
+            stored_len: BLOCK_SIZE as u64,
+            is_plain: true,
+        })
+    );
+    assert_eq!(index.entry(65), None);
+
+    assert_eq!(index.block_for_offset(0), Some(0));
+    assert_eq!(index.block_for_offset(BLOCK_SIZE as u64 * 3 + 5), Some(3));
+    assert_eq!(index.block_for_offset(ISO_SIZE as u64 - 1), Some(64));
+    assert_eq!(index.block_for_offset(ISO_SIZE as u64), None);
+    assert_eq!(index.block_range(64), 64 * 2048..64 * 2048 + 100);
+
+    // Storing the index gives back the same bytes
+    let mut stored = Vec::new();
+    index.write_into(&mut stored)?;
+    assert_eq!(stored, cso[0x18..0x18 + 66 * 4]);
+
+    let reader = CisoReader::new(File::open(&cso_path)?)?;
+    assert_eq!(reader.index(), &index);
+
+    Ok(())
+}
+
+#[test]
+fn ciso_index_invariants() -> Result<(), CisoError> {
+    let header = CisoHeader::with_layout(3 * BLOCK_SIZE as u64, 0x800, 0);
+    let file_len = 0x18 + 4 * 4 + 3 * BLOCK_SIZE as u64;
+
+    let mut index = CisoIndex::new(header);
+    let start = index.data_start();
+    index.push(start, true)?;
+    index.push(start + BLOCK_SIZE as u64, false)?;
+    assert!(matches!(
+        index.validate(file_len),
+        Err(CisoError::IncompleteIndex {
+            expected: 4,
+            actual: 2
+        })
+    ));
+
+    index.push(start + 100, false)?;
+    index.push(start + 200, false)?;
+    assert!(matches!(
+        index.validate(file_len),
+        Err(CisoError::NonMonotonicIndex { block: 2, .. })
+    ));
+
+    let mut index = CisoIndex::new(header);
+    for offset in [start, start + 2048, start + 3000, file_len + 1] {
+        index.push(offset, false)?;
+    }
+    assert!(matches!(
+        index.validate(file_len),
+        Err(CisoError::IndexOutOfBounds { block: 3, .. })
+    ));
+
+    // Plain blocks must be stored in full
+    let mut index = CisoIndex::new(header);
+    for offset in [start, start + 1000, start + 3000, start + 4000] {
+        index.push(offset, true)?;
+    }
+    assert!(matches!(
+        index.validate(file_len),
+        Err(CisoError::SizeMismatch {
+            block: 0,
+            expected: 2048,
+            actual: 1000
+        })
+    ));
+
+    // Offsets beyond 31 bits don't fit the entries
+    assert!(matches!(
+        CisoIndex::new(header).push(1 << 31, false),
+        Err(CisoError::ImageTooLarge { align: 0, .. })
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+use ciso_rs::{
+    CisoError, CisoHeader, CisoReader, CompressOptions, check_ciso, compress_ciso_with,
+    decompress_ciso,
+};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+#[test]
+fn ciso_min_align() {
+    assert_eq!(CisoHeader::min_align(0, 0x800), 0);
+    assert_eq!(CisoHeader::min_align(GIB, 0x800), 0);
+    assert_eq!(CisoHeader::min_align(2 * GIB, 0x800), 1);
+    assert_eq!(CisoHeader::min_align(4 * GIB, 0x800), 2);
+    assert_eq!(CisoHeader::min_align(8 * GIB, 0x800), 3);
+    assert_eq!(CisoHeader::min_align(u64::MAX, 1), 31);
+}
+
+#[test]
+fn ciso_sparse_multi_gib_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    let size = 5 * GIB;
+    let markers = [(0, *b"head"), (2 * GIB + 123, *b"past"), (size - 4, *b"tail")];
+
+    {
+        let mut iso = File::create(&iso_path)?;
+        iso.set_len(size)?;
+        for (pos, marker) in markers {
+            iso.seek(SeekFrom::Start(pos))?;
+            iso.write_all(&marker)?;
+        }
+    }
+
+    // Blocks padded to half their size, so that the output reaches past 2 GiB as well
+    let options = CompressOptions::new()
+        .level(1)
+        .block_size(0x10_0000)
+        .align(19);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+    check_ciso(File::open(&cso_path)?, true)?;
+
+    let mut reader = CisoReader::new(File::open(&cso_path)?)?;
+    assert_eq!(reader.header().align, 19);
+    assert_eq!(reader.len(), size);
+    let index = reader.index();
+    assert!(index.offset(index.len() - 1) > 2 * GIB);
+
+    let mut expected = Matches(BufReader::new(File::open(&iso_path)?));
+    decompress_ciso(File::open(&cso_path)?, &mut expected)?;
+    assert_eq!(expected.0.read(&mut [0u8; 1])?, 0);
+
+    for (pos, marker) in markers {
+        let mut buf = [0u8; 4];
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, marker);
+    }
+
+    Ok(())
+}
+
+/// Output asserting that what is written to it matches the content of the inner reader.
+struct Matches<R>(R);
+
+impl<R: Read> Write for Matches<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut expected = vec![0u8; buf.len()];
+        self.0.read_exact(&mut expected)?;
+        assert!(expected == buf, "decompressed image differs from the input");
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
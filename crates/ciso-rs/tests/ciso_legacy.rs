@@ -0,0 +1,227 @@
+use std::io::{Cursor, Read, Write};
+
+use ciso_rs::{
+    BlockCodec, CheckOptions, CisoError, CisoReader, CompressOptions, DecompressOptions,
+    LegacyFormat, LegacyReader, check_legacy, convert_legacy, decompress_legacy,
+};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+const DAX_FRAME: usize = 0x2000;
+const JSO_BLOCK: usize = 0x800;
+
+/// LZO1X stream of a zeroed 2 KiB block: one literal repeated by a single long match.
+const LZO_ZERO_BLOCK: [u8; 16] = [
+    18, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0, 229, 0x00, 0x00, 0x11, 0x00, 0x00,
+];
+
+/// Image of 2 KiB units alternating zeros and random bytes, ending with a partial random one.
+fn make_image(units: usize) -> Vec<u8> {
+    let mut image = vec![0u8; units * JSO_BLOCK + 100];
+    for (i, unit) in image.chunks_mut(JSO_BLOCK).enumerate() {
+        if i % 2 == 1 || unit.len() < JSO_BLOCK {
+            getrandom::fill(unit).unwrap();
+        }
+    }
+    image
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn push_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&u32::try_from(value).unwrap().to_le_bytes());
+}
+
+/// DAX version 1 image, the frames of `nc_areas` being stored uncompressed.
+fn make_dax(image: &[u8], nc_areas: &[(usize, usize)]) -> Vec<u8> {
+    let frames: Vec<&[u8]> = image.chunks(DAX_FRAME).collect();
+    let is_plain = |i: usize| {
+        nc_areas
+            .iter()
+            .any(|&(first, n)| (first..first + n).contains(&i))
+    };
+
+    let mut dax = b"DAX\0".to_vec();
+    push_u32(&mut dax, image.len());
+    push_u32(&mut dax, 1);
+    push_u32(&mut dax, nc_areas.len());
+    dax.resize(0x20, 0);
+
+    let stored: Vec<Vec<u8>> = (0..frames.len())
+        .map(|i| {
+            if is_plain(i) {
+                frames[i].to_vec()
+            } else {
+                zlib(frames[i])
+            }
+        })
+        .collect();
+
+    let mut offset = 0x20 + frames.len() * 6 + nc_areas.len() * 8;
+    for frame in &stored {
+        push_u32(&mut dax, offset);
+        offset += frame.len();
+    }
+    for frame in &stored {
+        dax.extend_from_slice(&u16::try_from(frame.len()).unwrap().to_le_bytes());
+    }
+    for &(first, n) in nc_areas {
+        push_u32(&mut dax, first);
+        push_u32(&mut dax, n);
+    }
+    for frame in &stored {
+        dax.extend_from_slice(frame);
+    }
+
+    dax
+}
+
+/// JSO image with LZO (0) or zlib (1) blocks, blocks which don't compress being stored as is.
+fn make_jso(image: &[u8], method: u8) -> Vec<u8> {
+    let blocks: Vec<&[u8]> = image.chunks(JSO_BLOCK).collect();
+
+    let mut jso = b"JISO\x03\x01".to_vec();
+    jso.extend_from_slice(&u16::try_from(JSO_BLOCK).unwrap().to_le_bytes());
+    jso.extend_from_slice(&[0, 0, method, 0]);
+    push_u32(&mut jso, image.len());
+    jso.resize(0x30, 0);
+
+    let stored: Vec<Vec<u8>> = blocks
+        .iter()
+        .map(|&block| {
+            let compressed = match method {
+                0 if block == [0u8; JSO_BLOCK] => LZO_ZERO_BLOCK.to_vec(),
+                0 => block.to_vec(),
+                _ => zlib(block),
+            };
+            if compressed.len() < block.len() {
+                compressed
+            } else {
+                block.to_vec()
+            }
+        })
+        .collect();
+
+    let mut offset = 0x30 + (blocks.len() + 1) * 4;
+    for block in &stored {
+        push_u32(&mut jso, offset);
+        offset += block.len();
+    }
+    push_u32(&mut jso, offset);
+    for block in &stored {
+        jso.extend_from_slice(block);
+    }
+
+    jso
+}
+
+/// Reads, fully checks and converts a legacy image, which must hold `image`.
+fn assert_legacy_image(file: &[u8], image: &[u8], format: LegacyFormat) -> Result<(), CisoError> {
+    let mut reader = LegacyReader::new(Cursor::new(file))?;
+    assert_eq!(reader.index().format(), format);
+    assert_eq!(reader.len(), image.len() as u64);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out)?;
+    assert_eq!(out, image);
+
+    let mut out = Vec::new();
+    decompress_legacy(Cursor::new(file), &mut out, &DecompressOptions::new())?;
+    assert_eq!(out, image);
+
+    let report = check_legacy(Cursor::new(file), &CheckOptions::new().full(true))?;
+    assert!(report.is_ok());
+    assert!(report.plain_blocks > 0 && report.compressed_blocks > 0);
+
+    let mut cso = Cursor::new(Vec::new());
+    convert_legacy(Cursor::new(file), &mut cso, &CompressOptions::new())?;
+
+    let mut out = Vec::new();
+    CisoReader::new(Cursor::new(cso.into_inner()))?.read_to_end(&mut out)?;
+    assert_eq!(out, image);
+
+    Ok(())
+}
+
+#[test]
+fn dax_read_check_and_convert() -> Result<(), CisoError> {
+    let image = make_image(22);
+    let dax = make_dax(&image, &[(1, 2)]);
+
+    let reader = LegacyReader::new(Cursor::new(&dax))?;
+    let codecs: Vec<_> = reader.index().iter().map(|block| block.codec).collect();
+    assert_eq!(
+        codecs,
+        [
+            BlockCodec::Zlib,
+            BlockCodec::Plain,
+            BlockCodec::Plain,
+            BlockCodec::Zlib,
+            BlockCodec::Zlib,
+            BlockCodec::Zlib,
+        ]
+    );
+
+    assert_legacy_image(&dax, &image, LegacyFormat::Dax)
+}
+
+#[test]
+fn jso_read_check_and_convert() -> Result<(), CisoError> {
+    let image = make_image(16);
+
+    for (method, codec) in [(0, BlockCodec::Lzo), (1, BlockCodec::Zlib)] {
+        let jso = make_jso(&image, method);
+
+        let reader = LegacyReader::new(Cursor::new(&jso))?;
+        assert_eq!(reader.index().block(0).codec, codec);
+        assert_eq!(reader.index().block(1).codec, BlockCodec::Plain);
+
+        assert_legacy_image(&jso, &image, LegacyFormat::Jso)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn legacy_damage_is_reported() -> Result<(), CisoError> {
+    let image = make_image(22);
+
+    // Garbage in the middle of the last zlib frame
+    let mut dax = make_dax(&image, &[]);
+    let len = dax.len();
+    dax[len - 200..len - 100].fill(0xff);
+
+    let report = check_legacy(Cursor::new(&dax), &CheckOptions::new().full(true))?;
+    assert_eq!(report.faulty_blocks.len(), 1);
+    assert_eq!(report.faulty_blocks[0].block, 5);
+    // Only the tables are checked otherwise
+    assert!(check_legacy(Cursor::new(&dax), &CheckOptions::new())?.is_ok());
+
+    // Tables larger than the file
+    assert!(matches!(
+        LegacyReader::new(Cursor::new(&dax[..0x30])),
+        Err(CisoError::TruncatedIndex {
+            total_blocks: 6,
+            ..
+        })
+    ));
+
+    let mut jso = make_jso(&image, 1);
+    jso[0x08] = 1;
+    assert!(matches!(
+        LegacyReader::new(Cursor::new(&jso)),
+        Err(CisoError::UnsupportedFeature { .. })
+    ));
+
+    jso[..4].copy_from_slice(b"JSO!");
+    assert!(matches!(
+        LegacyReader::new(Cursor::new(&jso)),
+        Err(CisoError::BadMagic { .. })
+    ));
+
+    Ok(())
+}
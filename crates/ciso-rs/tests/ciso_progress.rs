@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+
+use ciso_rs::{
+    CheckOptions, CisoError, CompressOptions, DecompressOptions, Progress, check_ciso_with,
+    compress_ciso_with, decompress_ciso_with,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 300 * BLOCK_SIZE;
+
+fn recorder() -> (Arc<Mutex<Vec<Progress>>>, impl Fn(&Progress) + Send + Sync) {
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let sink = updates.clone();
+    (updates, move |p: &Progress| sink.lock().unwrap().push(*p))
+}
+
+fn assert_complete(updates: &[Progress], bytes_in: u64, bytes_out: u64) {
+    assert_eq!(updates.len(), ISO_SIZE / BLOCK_SIZE);
+    assert!(updates.windows(2).all(|w| {
+        w[0].blocks_done + 1 == w[1].blocks_done
+            && w[0].bytes_in <= w[1].bytes_in
+            && w[0].bytes_out <= w[1].bytes_out
+    }));
+
+    let last = updates.last().unwrap();
+    assert_eq!(last.blocks_done, last.total_blocks);
+    assert_eq!(last.bytes_in, bytes_in);
+    assert_eq!(last.bytes_out, bytes_out);
+}
+
+#[test]
+fn ciso_progress_reports_every_block() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    let out_path = tmp.path().join("output.iso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+
+    let (updates, observer) = recorder();
+    let options = CompressOptions::new().progress(observer);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+    let cso_len = std::fs::metadata(&cso_path)?.len();
+    assert_complete(&updates.lock().unwrap(), ISO_SIZE as u64, cso_len);
+    assert!(updates.lock().unwrap().last().unwrap().ratio() < 1.0);
+
+    // Decompression and check consume the data section, without header and index
+    let data_len = cso_len - 0x18 - (ISO_SIZE / BLOCK_SIZE + 1) as u64 * 4;
+
+    let (updates, observer) = recorder();
+    let options = DecompressOptions::new().progress(observer);
+    decompress_ciso_with(File::open(&cso_path)?, File::create(&out_path)?, &options)?;
+    assert_complete(&updates.lock().unwrap(), data_len, ISO_SIZE as u64);
+
+    let (updates, observer) = recorder();
+    let options = CheckOptions::new().full(true).progress(observer);
+    check_ciso_with(File::open(&cso_path)?, &options)?;
+    assert_complete(&updates.lock().unwrap(), data_len, ISO_SIZE as u64);
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::thread;
+
+use ciso_rs::{
+    BlockCodec, CacheConfig, CacheStats, CisoError, CisoImage, CisoReader, SECTOR_SIZE,
+    compress_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 4 * 1024 * 1024 + 123; // Partial final block
+
+#[test]
+fn ciso_reader_random_access() -> std::io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    let orig = fs::read(&iso_path)?;
+    let mut reader = CisoReader::new(File::open(&cso_path)?)?;
+
+    assert_eq!(reader.len(), ISO_SIZE as u64);
+
+    // Reads spanning block boundaries, backwards seeks and the image tail
+    for (start, len) in [
+        (0, 10),
+        (BLOCK_SIZE - 5, 10),
+        (3 * BLOCK_SIZE + 17, 5 * BLOCK_SIZE),
+        (1, 1),
+        (ISO_SIZE - 200, 200),
+    ] {
+        let mut buf = vec![0u8; len];
+        reader.seek(SeekFrom::Start(start as u64))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, orig[start..start + len]);
+    }
+
+    let mut buf = [0u8; 16];
+    reader.seek(SeekFrom::End(-4))?;
+    assert_eq!(reader.read(&mut buf)?, 4);
+    assert_eq!(reader.read(&mut buf)?, 0);
+
+    let mut all = Vec::new();
+    reader.rewind()?;
+    reader.read_to_end(&mut all)?;
+    assert_eq!(all, orig);
+
+    Ok(())
+}
+
+#[test]
+fn ciso_image_concurrent_read_at() -> std::io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    let orig = fs::read(&iso_path)?;
+    let image = CisoImage::open(File::open(&cso_path)?)?;
+
+    thread::scope(|s| {
+        for t in 0..4 {
+            let image = &image;
+            let orig = &orig;
+
+            s.spawn(move || {
+                for lba in (t..ISO_SIZE / SECTOR_SIZE).step_by(7) {
+                    let sectors = image.read_sectors(lba as u64, 1).unwrap();
+                    let start = lba * SECTOR_SIZE;
+                    assert_eq!(sectors, orig[start..start + SECTOR_SIZE]);
+                }
+            });
+        }
+    });
+
+    let mut tail = [0u8; 64];
+    let n = image.read_at(ISO_SIZE as u64 - 10, &mut tail)?;
+    assert_eq!(tail[..n], orig[ISO_SIZE - 10..]);
+    assert!(
+        image
+            .read_sectors((ISO_SIZE / SECTOR_SIZE) as u64, 1)
+            .is_err()
+    );
+    // Out of range before any allocation, without overflowing
+    for (lba, count) in [
+        (u64::MAX, 1),
+        (0, usize::MAX),
+        (1, usize::MAX / SECTOR_SIZE),
+    ] {
+        assert!(matches!(
+            image.read_sectors(lba, count),
+            Err(CisoError::SectorsOutOfRange { .. })
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_image_block_cache() -> std::io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    let orig = fs::read(&iso_path)?;
+    let image = CisoImage::with_cache(
+        File::open(&cso_path)?,
+        CacheConfig::blocks(16).read_ahead(4),
+    )?;
+
+    // Repeated small reads in one block only inflate it once
+    let mut buf = [0u8; 16];
+    image.read_exact_at(100, &mut buf)?;
+    image.read_exact_at(200, &mut buf)?;
+    assert_eq!(image.cache_stats(), CacheStats { hits: 1, misses: 1 });
+
+    // Sequential reads are served by read-ahead of the next blocks
+    for lba in 1..6 {
+        let sector = image.read_sectors(lba as u64, 1)?;
+        let start = lba * SECTOR_SIZE;
+        assert_eq!(sector, orig[start..start + SECTOR_SIZE]);
+    }
+    assert_eq!(image.cache_stats(), CacheStats { hits: 6, misses: 1 });
+
+    // Prefetched blocks are served from the cache
+    let start = 100 * BLOCK_SIZE;
+    image.prefetch(start as u64..(start + 8 * BLOCK_SIZE) as u64)?;
+    let sectors = image.read_sectors(100, 8)?;
+    assert_eq!(sectors, orig[start..start + 8 * BLOCK_SIZE]);
+    assert_eq!(
+        image.cache_stats(),
+        CacheStats {
+            hits: 14,
+            misses: 1
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ciso_image_read_ahead_skips_damaged_blocks() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    // Garbage over a deflate block, read ahead of the two valid blocks before it
+    let mut cso = fs::read(&cso_path)?;
+    let index = CisoImage::open(File::open(&cso_path)?)?.index().clone();
+    let damaged = (2..index.len())
+        .find(|&i| index.codec(i) == BlockCodec::Deflate)
+        .unwrap();
+    let offset = usize::try_from(index.offset(damaged)).unwrap();
+    cso[offset..offset + 8].fill(0xff);
+    fs::write(&cso_path, &cso)?;
+
+    let orig = fs::read(&iso_path)?;
+    let image = CisoImage::with_cache(
+        File::open(&cso_path)?,
+        CacheConfig::blocks(16).read_ahead(4),
+    )?;
+
+    for block in [damaged - 2, damaged - 1] {
+        let mut buf = [0u8; 16];
+        image.read_exact_at((block * BLOCK_SIZE) as u64, &mut buf)?;
+        assert_eq!(buf, orig[block * BLOCK_SIZE..block * BLOCK_SIZE + 16]);
+    }
+
+    // Only reading the damaged block reports it
+    let mut buf = [0u8; 16];
+    assert!(matches!(
+        image.read_exact_at((damaged * BLOCK_SIZE) as u64, &mut buf),
+        Err(CisoError::CorruptBlock { block, .. }) if block == damaged
+    ));
+
+    Ok(())
+}
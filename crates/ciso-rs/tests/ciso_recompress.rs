@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::{Cursor, Read};
+
+use ciso_rs::{
+    BlockCodec, CisoError, CisoFormat, CisoReader, CompressOptions, RecompressOptions,
+    compress_ciso_stream, recompress_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 1024 * 1024 + 1234; // Partial final block
+
+fn fake_iso() -> Result<Vec<u8>, CisoError> {
+    let tmp = tempfile::tempdir()?;
+    let path = tmp.path().join("input.iso");
+    make_fake_iso(&path, ISO_SIZE, BLOCK_SIZE)?;
+    Ok(fs::read(&path)?)
+}
+
+fn compress(iso: &[u8], options: &CompressOptions) -> Result<Vec<u8>, CisoError> {
+    let mut out = Cursor::new(Vec::new());
+    compress_ciso_stream(Cursor::new(iso), &mut out, options)?;
+    Ok(out.into_inner())
+}
+
+/// Recompresses `cso`, checking that the output holds `iso`, and returns it with its report.
+fn recompress(
+    cso: &[u8],
+    iso: &[u8],
+    options: &RecompressOptions,
+) -> Result<(Vec<u8>, usize), CisoError> {
+    let mut out = Cursor::new(Vec::new());
+    let report = recompress_ciso(Cursor::new(cso), &mut out, options)?;
+    let out = out.into_inner();
+
+    let mut image = Vec::new();
+    CisoReader::new(Cursor::new(&out))?.read_to_end(&mut image)?;
+    assert_eq!(image, iso);
+
+    Ok((out, report.copied_blocks))
+}
+
+fn codecs(cso: &[u8]) -> Result<Vec<BlockCodec>, CisoError> {
+    let reader = CisoReader::new(Cursor::new(cso))?;
+    let index = reader.index();
+    Ok((0..index.len()).map(|i| index.codec(i)).collect())
+}
+
+#[test]
+fn unchanged_blocks_are_copied() -> Result<(), CisoError> {
+    let iso = fake_iso()?;
+    let cso = compress(&iso, &CompressOptions::new())?;
+    let total_blocks = ISO_SIZE.div_ceil(BLOCK_SIZE);
+
+    // Same settings, the very same image comes out
+    let (out, copied) = recompress(&cso, &iso, &RecompressOptions::new())?;
+    assert_eq!(copied, total_blocks);
+    assert_eq!(out, cso);
+
+    // Deflate blocks are valid CSO v2 ones, plain ones are tried with LZ4 too
+    let options =
+        RecompressOptions::new().compress(CompressOptions::new().format(CisoFormat::Cso2));
+    let (out, copied) = recompress(&cso, &iso, &options)?;
+    let deflated = codecs(&cso)?
+        .iter()
+        .filter(|&&codec| codec == BlockCodec::Deflate)
+        .count();
+    assert_eq!(copied, deflated);
+    assert_eq!(
+        CisoReader::new(Cursor::new(&out))?.header().format(),
+        CisoFormat::Cso2
+    );
+
+    Ok(())
+}
+
+#[test]
+fn copied_blocks_leave_the_padding_behind() -> Result<(), CisoError> {
+    let iso = fake_iso()?;
+    let padded = compress(&iso, &CompressOptions::new().align(6))?;
+    let packed = compress(&iso, &CompressOptions::new().align(0))?;
+    assert!(packed.len() < padded.len());
+
+    // Only the streams are copied, the same blocks as compressing anew
+    let options = RecompressOptions::new().compress(CompressOptions::new().align(0));
+    let (out, copied) = recompress(&padded, &iso, &options)?;
+    assert_eq!(copied, ISO_SIZE.div_ceil(BLOCK_SIZE));
+    assert_eq!(out, packed);
+
+    Ok(())
+}
+
+#[test]
+fn other_codecs_and_layouts_are_recompressed() -> Result<(), CisoError> {
+    let iso = fake_iso()?;
+    let cso = compress(&iso, &CompressOptions::new())?;
+
+    // ZSO holds no deflate block
+    let options = RecompressOptions::new().compress(CompressOptions::new().format(CisoFormat::Zso));
+    let (zso, copied) = recompress(&cso, &iso, &options)?;
+    assert_eq!(copied, 0);
+    assert!(codecs(&zso)?.contains(&BlockCodec::Lz4));
+
+    // And back, through CSO v2 which keeps the LZ4 blocks
+    let options =
+        RecompressOptions::new().compress(CompressOptions::new().format(CisoFormat::Cso2));
+    let (_, copied) = recompress(&zso, &iso, &options)?;
+    assert!(copied > 0);
+
+    // A new level applies to every block
+    let options = RecompressOptions::new().compress(CompressOptions::new().level(9));
+    let (_, copied) = recompress(&cso, &iso, &options)?;
+    assert_eq!(copied, 0);
+
+    let (_, copied) = recompress(&cso, &iso, &RecompressOptions::new().reencode_all(true))?;
+    assert_eq!(copied, 0);
+
+    let options = RecompressOptions::new().compress(CompressOptions::new().block_size(0x4000));
+    let (out, copied) = recompress(&cso, &iso, &options)?;
+    assert_eq!(copied, 0);
+    assert_eq!(
+        CisoReader::new(Cursor::new(&out))?.header().block_size,
+        0x4000
+    );
+
+    Ok(())
+}
+
+#[test]
+fn damaged_blocks_are_not_copied() -> Result<(), CisoError> {
+    let iso = fake_iso()?;
+    let mut cso = compress(&iso, &CompressOptions::new())?;
+
+    // Garbage over the first deflate block
+    let reader = CisoReader::new(Cursor::new(&cso))?;
+    let block = (0..reader.index().len())
+        .find(|&i| reader.index().codec(i) == BlockCodec::Deflate)
+        .unwrap();
+    let offset = usize::try_from(reader.index().offset(block)).unwrap();
+    cso[offset..offset + 8].fill(0xff);
+
+    let result = recompress_ciso(
+        Cursor::new(&cso),
+        Cursor::new(Vec::new()),
+        &RecompressOptions::new(),
+    );
+    assert!(matches!(
+        result,
+        Err(CisoError::CorruptBlock { block: b, .. }) if b == block
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use ciso_rs::{
+    CisoError, CisoFormat, CompressOptions, RepairOptions, RepairReport, check_ciso, compress_ciso,
+    compress_ciso_with, decompress_ciso, repair_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 64 * BLOCK_SIZE + 100;
+const INDEX_POS: usize = 0x18;
+
+fn index_entry(cso: &[u8], i: usize) -> u32 {
+    let pos = INDEX_POS + i * 4;
+    u32::from_le_bytes(cso[pos..pos + 4].try_into().unwrap())
+}
+
+fn set_index_entry(cso: &mut [u8], i: usize, v: u32) {
+    let pos = INDEX_POS + i * 4;
+    cso[pos..pos + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+/// Repairs `cso`, checks the result and returns its decompressed content.
+fn repair(
+    dir: &Path,
+    cso: &[u8],
+    options: &RepairOptions,
+) -> Result<(RepairReport, Vec<u8>), CisoError> {
+    let damaged_path = dir.join("damaged.cso");
+    let repaired_path = dir.join("repaired.cso");
+    let out_path = dir.join("repaired.iso");
+
+    fs::write(&damaged_path, cso)?;
+    let report = repair_ciso(
+        File::open(&damaged_path)?,
+        File::create(&repaired_path)?,
+        options,
+    )?;
+
+    check_ciso(File::open(&repaired_path)?, true)?;
+    decompress_ciso(File::open(&repaired_path)?, File::create(&out_path)?)?;
+
+    Ok((report, fs::read(&out_path)?))
+}
+
+#[test]
+fn ciso_repair_replaces_corrupt_blocks() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    let iso = fs::read(&iso_path)?;
+    let mut cso = fs::read(&cso_path)?;
+
+    let (healthy, data) = repair(tmp.path(), &cso, &RepairOptions::new())?;
+    assert!(healthy.is_complete());
+    assert!(!healthy.index_rebuilt);
+    assert_eq!(healthy.total_blocks, 65);
+    assert_eq!(data, iso);
+
+    // Blocks 3 and 4 are compressed and contiguous, block 9 is on its own
+    for block in [3, 4, 9] {
+        let off = (index_entry(&cso, block) & 0x7fff_ffff) as usize;
+        cso[off..off + 4].fill(0xff);
+    }
+
+    let (report, data) = repair(tmp.path(), &cso, &RepairOptions::new())?;
+    assert!(!report.index_rebuilt);
+    assert_eq!(report.lost_blocks, [3, 4, 9]);
+    assert_eq!(report.lost_sectors, [3..5, 9..10]);
+
+    let mut expected = iso.clone();
+    for block in [3, 4, 9] {
+        expected[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].fill(0);
+    }
+    assert_eq!(data, expected);
+
+    // Lost blocks come back from a reference image
+    let options = RepairOptions::new().reference(File::open(&iso_path)?);
+    let (report, data) = repair(tmp.path(), &cso, &options)?;
+    assert_eq!(report.lost_blocks, [3, 4, 9]);
+    assert_eq!(data, iso);
+
+    Ok(())
+}
+
+#[test]
+fn ciso_repair_rebuilds_damaged_index() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    let iso = fs::read(&iso_path)?;
+    let cso = fs::read(&cso_path)?;
+
+    let mut damaged = cso.clone();
+    for i in 10..20 {
+        set_index_entry(&mut damaged, i, 0xdead_beef);
+    }
+
+    let (report, data) = repair(tmp.path(), &damaged, &RepairOptions::new())?;
+    assert!(report.index_rebuilt);
+    assert!(report.is_complete());
+    assert_eq!(data, iso);
+
+    // A corrupt stream is skipped, the scan resyncs on the next block
+    let off = (index_entry(&cso, 30) & 0x7fff_ffff) as usize;
+    damaged[off..off + 4].fill(0xff);
+
+    let (report, data) = repair(tmp.path(), &damaged, &RepairOptions::new())?;
+    assert!(report.index_rebuilt);
+    assert_eq!(report.lost_blocks, [30]);
+    assert_eq!(data[..30 * BLOCK_SIZE], iso[..30 * BLOCK_SIZE]);
+    assert_eq!(data[31 * BLOCK_SIZE..], iso[31 * BLOCK_SIZE..]);
+
+    Ok(())
+}
+
+#[test]
+fn lz4_images_rebuild_damaged_index() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    let iso = fs::read(&iso_path)?;
+
+    // ZSO only holds LZ4 blocks, CSO v2 mixes them with deflate ones
+    for format in [CisoFormat::Zso, CisoFormat::Cso2] {
+        let options = CompressOptions::new().format(format).align(0);
+        compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+        let mut damaged = fs::read(&cso_path)?;
+        for i in 10..20 {
+            set_index_entry(&mut damaged, i, 0xdead_beef);
+        }
+
+        let (report, data) = repair(tmp.path(), &damaged, &RepairOptions::new())?;
+        assert!(report.index_rebuilt);
+        assert!(report.is_complete());
+        assert_eq!(data, iso);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn repair_levels_follow_the_format() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    let iso = fs::read(&iso_path)?;
+    let options = RepairOptions::new().level(12);
+
+    // LZ4 HC levels go up to 12
+    let zso = CompressOptions::new().format(CisoFormat::Zso);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &zso)?;
+    let (report, data) = repair(tmp.path(), &fs::read(&cso_path)?, &options)?;
+    assert!(report.is_complete());
+    assert_eq!(data, iso);
+
+    // Deflate stops at 9
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+    assert!(matches!(
+        repair(tmp.path(), &fs::read(&cso_path)?, &options),
+        Err(CisoError::InvalidOption {
+            name: "level",
+            value: 12
+        })
+    ));
+    assert!(RepairOptions::new().level(13).validate().is_err());
+
+    Ok(())
+}
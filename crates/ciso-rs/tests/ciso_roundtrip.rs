@@ -1,35 +1,17 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
 
-use ciso_rs::{check_ciso, compress_ciso, decompress_ciso};
+use ciso_rs::{
+    CheckOptions, CisoError, CisoReader, CompressOptions, DecompressOptions, check_ciso,
+    check_ciso_stream, compress_ciso, compress_ciso_spooled, compress_ciso_stream,
+    compress_ciso_with, decompress_ciso, decompress_ciso_stream, decompress_ciso_with,
+};
 
-const BLOCK_SIZE: usize = 2048;
-const ISO_SIZE: usize = 32 * 1024 * 1024; // 32 MiB
+use crate::common::{BLOCK_SIZE, make_fake_iso};
 
-#[expect(clippy::cast_possible_truncation)]
-fn make_fake_iso(path: &PathBuf, size: usize, block_size: usize) -> std::io::Result<()> {
-    let mut file = File::create(path)?;
-
-    for i in 0..(size / block_size) {
-        let mut block = vec![0u8; block_size];
-
-        // Alternate compressible and uncompressible blocks
-        if i % 3 == 0 {
-            block.fill(0);
-        } else if i % 3 == 1 {
-            for (j, b) in block.iter_mut().enumerate() {
-                *b = (j as u8).wrapping_add(i as u8);
-            }
-        } else {
-            getrandom::fill(&mut block).unwrap();
-        }
-
-        file.write_all(&block)?;
-    }
+mod common;
 
-    Ok(())
-}
+const ISO_SIZE: usize = 32 * 1024 * 1024; // 32 MiB
 
 #[test]
 fn ciso_compress_check_decompress_roundtrip() -> std::io::Result<()> {
@@ -56,3 +38,244 @@ fn ciso_compress_check_decompress_roundtrip() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn ciso_compress_with_options_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    let out_path = tmp.path().join("output.iso");
+
+    let block_size = 0x4000;
+    make_fake_iso(&iso_path, 256 * block_size, block_size)?;
+
+    let options = CompressOptions::new()
+        .level(9)
+        .block_size(0x4000)
+        .align(4)
+        .threads(2)
+        .queue_depth(3);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+    let reader = CisoReader::new(File::open(&cso_path)?)?;
+    assert_eq!(reader.header().block_size, 0x4000);
+    assert_eq!(reader.header().align, 4);
+
+    let iso_len = std::fs::metadata(&iso_path)?.len();
+    assert!(std::fs::metadata(&cso_path)?.len() <= options.max_output_size(iso_len));
+
+    // Stored blocks only, the worst case
+    let stored = options.clone().level(0);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &stored)?;
+    assert!(std::fs::metadata(&cso_path)?.len() <= stored.max_output_size(iso_len));
+
+    check_ciso(File::open(&cso_path)?, true)?;
+    decompress_ciso(File::open(&cso_path)?, File::create(&out_path)?)?;
+
+    assert_eq!(std::fs::read(&iso_path)?, std::fs::read(&out_path)?);
+
+    for options in [
+        CompressOptions::new().level(10),
+        CompressOptions::new().block_size(3000),
+        CompressOptions::new().threads(0),
+    ] {
+        assert!(matches!(
+            options.validate(),
+            Err(CisoError::InvalidOption { .. })
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_odd_sizes_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    let out_path = tmp.path().join("output.iso");
+
+    // Empty, single partial block, single full block, partial final block
+    for size in [
+        0,
+        1,
+        BLOCK_SIZE - 1,
+        BLOCK_SIZE,
+        BLOCK_SIZE + 1,
+        7 * BLOCK_SIZE + 123,
+    ] {
+        make_fake_iso(&iso_path, size, BLOCK_SIZE)?;
+
+        compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+        let reader = CisoReader::new(File::open(&cso_path)?)?;
+        assert_eq!(reader.len(), size as u64);
+
+        check_ciso(File::open(&cso_path)?, true)?;
+        decompress_ciso(File::open(&cso_path)?, File::create(&out_path)?)?;
+
+        assert_eq!(
+            std::fs::read(&iso_path)?,
+            std::fs::read(&out_path)?,
+            "size {size}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_parallel_decompress_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    let out_path = tmp.path().join("output.iso");
+
+    make_fake_iso(&iso_path, 1024 * BLOCK_SIZE + 100, BLOCK_SIZE)?;
+    compress_ciso(File::open(&iso_path)?, File::create(&cso_path)?, 6)?;
+
+    for threads in [1, 3] {
+        let options = DecompressOptions::new().threads(threads);
+        decompress_ciso_with(File::open(&cso_path)?, File::create(&out_path)?, &options)?;
+        assert_eq!(std::fs::read(&iso_path)?, std::fs::read(&out_path)?);
+    }
+
+    // Whichever worker fails first, the first corrupt block is reported
+    let mut cso = std::fs::read(&cso_path)?;
+    for block in [900, 300] {
+        let pos = 0x18 + block * 4;
+        let off = u32::from_le_bytes(cso[pos..pos + 4].try_into().unwrap()) as usize;
+        cso[off..off + 4].fill(0xff);
+    }
+    std::fs::write(&cso_path, &cso)?;
+
+    let options = DecompressOptions::new().threads(3);
+    let err = decompress_ciso_with(File::open(&cso_path)?, File::create(&out_path)?, &options);
+    assert!(matches!(
+        err,
+        Err(CisoError::CorruptBlock { block: 300, .. })
+    ));
+
+    assert!(matches!(
+        DecompressOptions::new().threads(0).validate(),
+        Err(CisoError::InvalidOption { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn ciso_in_memory_stream_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, 300 * BLOCK_SIZE + 123, BLOCK_SIZE)?;
+    let iso = fs::read(&iso_path)?;
+
+    let options = CompressOptions::new().align(2);
+    let mut cso = Cursor::new(Vec::new());
+    compress_ciso_stream(Cursor::new(&iso), &mut cso, &options)?;
+    let cso = cso.into_inner();
+
+    // Same bytes as the mapped file path
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+    assert_eq!(cso, fs::read(&cso_path)?);
+
+    check_ciso_stream(Cursor::new(&cso), &CheckOptions::new().full(true))?;
+
+    let mut out = Vec::new();
+    decompress_ciso_stream(Cursor::new(&cso), &mut out, &DecompressOptions::new())?;
+    assert_eq!(out, iso);
+
+    // Corrupted blocks are reported by the full check of a stream too
+    let mut corrupt = cso.clone();
+    let data_start = 0x18 + (301 + 1) * 4;
+    corrupt[data_start + 4..data_start + 64].fill(0xff);
+    assert!(matches!(
+        check_ciso_stream(Cursor::new(&corrupt), &CheckOptions::new().full(true)),
+        Err(CisoError::CorruptBlock { block: 0, .. })
+    ));
+    check_ciso_stream(Cursor::new(&corrupt), &CheckOptions::new())?;
+
+    Ok(())
+}
+
+#[test]
+fn ciso_spooled_pipe_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+
+    make_fake_iso(&iso_path, 300 * BLOCK_SIZE + 123, BLOCK_SIZE)?;
+    let iso = fs::read(&iso_path)?;
+
+    // Neither `&[u8]` nor `Vec<u8>` can seek
+    let options = CompressOptions::new().align(2);
+    let mut cso = Vec::new();
+    compress_ciso_spooled(&iso[..], &mut cso, Cursor::new(Vec::new()), &options)?;
+
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+    assert_eq!(cso, fs::read(&cso_path)?);
+
+    let mut out = Vec::new();
+    decompress_ciso_with(
+        File::open(&cso_path)?,
+        &mut out,
+        &DecompressOptions::new().threads(3),
+    )?;
+    assert_eq!(out, iso);
+
+    // Whole blocks and empty inputs
+    for size in [0, 4 * BLOCK_SIZE] {
+        let mut cso = Vec::new();
+        let spool = tempfile::tempfile()?;
+        compress_ciso_spooled(&iso[..size], &mut cso, spool, &CompressOptions::new())?;
+
+        let mut out = Vec::new();
+        decompress_ciso_stream(Cursor::new(&cso), &mut out, &DecompressOptions::new())?;
+        assert_eq!(out, iso[..size]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ciso_short_blocks_are_reported() -> Result<(), CisoError> {
+    let compress = |iso: &[u8]| -> Result<Vec<u8>, CisoError> {
+        let mut cso = Cursor::new(Vec::new());
+        compress_ciso_stream(Cursor::new(iso), &mut cso, &CompressOptions::new())?;
+        Ok(cso.into_inner())
+    };
+
+    // The stream of a 100 bytes block, in place of the first one of a full image
+    let short = compress(&[0u8; 100])?;
+    let short = {
+        let index = CisoReader::new(Cursor::new(&short))?.index().clone();
+        let start = usize::try_from(index.offset(0)).unwrap();
+        short[start..usize::try_from(index.offset(1)).unwrap()].to_vec()
+    };
+
+    let mut cso = compress(&[0u8; 2 * BLOCK_SIZE])?;
+    let index = CisoReader::new(Cursor::new(&cso))?.index().clone();
+    let start = usize::try_from(index.offset(0)).unwrap();
+    assert!(short.len() <= usize::try_from(index.offset(1)).unwrap() - start);
+    cso[start..start + short.len()].copy_from_slice(&short);
+
+    let result = decompress_ciso_stream(Cursor::new(&cso), io::sink(), &DecompressOptions::new());
+    assert!(matches!(
+        result,
+        Err(CisoError::SizeMismatch {
+            block: 0,
+            expected: 2048,
+            actual: 100,
+        })
+    ));
+
+    Ok(())
+}
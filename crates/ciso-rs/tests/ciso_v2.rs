@@ -0,0 +1,107 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+
+use ciso_rs::{
+    BlockCodec, CheckOptions, CisoError, CisoFormat, CisoReader, CompressOptions,
+    check_ciso_report, compress_ciso_spooled, compress_ciso_with, decompress_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 4 * 1024 * 1024 + 1234; // Partial final block
+
+#[test]
+fn cso2_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let out_path = tmp.path().join("output.iso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    let orig = fs::read(&iso_path)?;
+
+    for (level, align) in [(0, 0), (6, 0), (9, 4)] {
+        let cso_path = tmp.path().join(format!("output-{level}-{align}.cso"));
+        let options = CompressOptions::new()
+            .format(CisoFormat::Cso2)
+            .level(level)
+            .align(align)
+            .threads(3);
+        compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+        let reader = CisoReader::new(File::open(&cso_path)?)?;
+        assert_eq!(reader.header().ver, 2);
+        assert_eq!(reader.header().format(), CisoFormat::Cso2);
+
+        let index = reader.index();
+        let codecs: Vec<_> = (0..index.len()).map(|i| index.codec(i)).collect();
+        assert!(codecs.contains(&BlockCodec::Plain));
+        assert!(
+            codecs
+                .iter()
+                .any(|&codec| codec == BlockCodec::Lz4 || codec == BlockCodec::Deflate)
+        );
+        // Deflate at level 0 only stores, LZ4 is always smaller
+        if level == 0 {
+            assert!(!codecs.contains(&BlockCodec::Deflate));
+        }
+
+        let report = check_ciso_report(File::open(&cso_path)?, &CheckOptions::new().full(true))?;
+        assert!(report.is_ok());
+
+        decompress_ciso(File::open(&cso_path)?, File::create(&out_path)?)?;
+        assert_eq!(fs::read(&out_path)?, orig);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cso2_spooled_matches_file_compression() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+
+    let options = CompressOptions::new().format(CisoFormat::Cso2).align(2);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+    let mut piped = Vec::new();
+    compress_ciso_spooled(
+        File::open(&iso_path)?,
+        &mut piped,
+        Cursor::new(Vec::new()),
+        &options,
+    )?;
+    assert_eq!(piped, fs::read(&cso_path)?);
+
+    let mut out = Vec::new();
+    CisoReader::new(Cursor::new(piped))?.read_to_end(&mut out)?;
+    assert_eq!(out, fs::read(&iso_path)?);
+
+    Ok(())
+}
+
+#[test]
+fn cso2_rejects_alignment_of_a_block() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let cso_path = tmp.path().join("output.cso");
+    make_fake_iso(&iso_path, BLOCK_SIZE * 4, BLOCK_SIZE)?;
+
+    // 1 << 11 is the block size, plain and compressed blocks could not be told apart
+    let options = CompressOptions::new().format(CisoFormat::Cso2).align(11);
+    assert!(matches!(
+        compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options),
+        Err(CisoError::InvalidOption { name: "align", .. })
+    ));
+
+    // Fine for v1
+    let options = CompressOptions::new().align(11);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&cso_path)?, &options)?;
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::fs::{self, File};
+
+use ciso_rs::{
+    BlockCodec, CheckOptions, CisoError, CisoFormat, CisoHeader, CisoIndex, CisoReader,
+    CompressOptions, RepairOptions, check_ciso_report, compress_ciso_with, decompress_ciso,
+    repair_ciso,
+};
+
+use crate::common::{BLOCK_SIZE, make_fake_iso};
+
+mod common;
+
+const ISO_SIZE: usize = 4 * 1024 * 1024 + 1234; // Partial final block
+
+#[test]
+fn zso_roundtrip() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let out_path = tmp.path().join("output.iso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+    let orig = fs::read(&iso_path)?;
+
+    // Fast LZ4, then LZ4 HC up to its best level
+    for level in [0, 6, 12] {
+        let zso_path = tmp.path().join(format!("output-{level}.zso"));
+        let options = CompressOptions::new()
+            .format(CisoFormat::Zso)
+            .level(level)
+            .threads(3);
+        compress_ciso_with(File::open(&iso_path)?, File::create(&zso_path)?, &options)?;
+
+        let reader = CisoReader::new(File::open(&zso_path)?)?;
+        assert_eq!(&reader.header().magic, b"ZISO");
+        assert_eq!(reader.header().ver, 1);
+        assert_eq!(reader.header().format(), CisoFormat::Zso);
+
+        let index = reader.index();
+        let codecs: Vec<_> = (0..index.len()).map(|i| index.codec(i)).collect();
+        assert!(codecs.contains(&BlockCodec::Plain));
+        assert!(codecs.contains(&BlockCodec::Lz4));
+        assert!(!codecs.contains(&BlockCodec::Deflate));
+
+        let report = check_ciso_report(File::open(&zso_path)?, &CheckOptions::new().full(true))?;
+        assert!(report.is_ok());
+
+        decompress_ciso(File::open(&zso_path)?, File::create(&out_path)?)?;
+        assert_eq!(fs::read(&out_path)?, orig);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn zso_levels_and_codecs_are_validated() {
+    let options = CompressOptions::new().format(CisoFormat::Zso).level(13);
+    assert!(matches!(
+        options.validate(),
+        Err(CisoError::InvalidOption {
+            name: "level",
+            value: 13
+        })
+    ));
+    // LZ4 HC levels above 9 are ZSO only
+    assert!(CompressOptions::new().level(12).validate().is_err());
+
+    let mut index = CisoIndex::new(CisoHeader::with_format(
+        0x800,
+        0x800,
+        0,
+        CisoFormat::Zso,
+    ));
+    assert!(matches!(
+        index.push_codec(index.data_start(), BlockCodec::Deflate),
+        Err(CisoError::UnsupportedCodec {
+            codec: BlockCodec::Deflate
+        })
+    ));
+
+    let mut header = *index.header();
+    header.ver = 2;
+    assert!(matches!(
+        header.validate(),
+        Err(CisoError::UnsupportedVersion { version: 2 })
+    ));
+}
+
+#[test]
+fn zso_repair_keeps_the_format() -> Result<(), CisoError> {
+    let tmp = tempfile::tempdir()?;
+
+    let iso_path = tmp.path().join("input.iso");
+    let zso_path = tmp.path().join("input.zso");
+    let repaired_path = tmp.path().join("input.repaired.zso");
+    make_fake_iso(&iso_path, ISO_SIZE, BLOCK_SIZE)?;
+
+    let options = CompressOptions::new().format(CisoFormat::Zso);
+    compress_ciso_with(File::open(&iso_path)?, File::create(&zso_path)?, &options)?;
+
+    let report = repair_ciso(
+        File::open(&zso_path)?,
+        File::create(&repaired_path)?,
+        &RepairOptions::new(),
+    )?;
+    assert!(report.is_complete());
+
+    let reader = CisoReader::new(File::open(&repaired_path)?)?;
+    assert_eq!(reader.header().format(), CisoFormat::Zso);
+
+    let out_path = tmp.path().join("output.iso");
+    decompress_ciso(File::open(&repaired_path)?, File::create(&out_path)?)?;
+    assert_eq!(fs::read(&out_path)?, fs::read(&iso_path)?);
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+pub const BLOCK_SIZE: usize = 2048;
+
+#[expect(clippy::cast_possible_truncation)]
+pub fn make_fake_iso(path: &Path, size: usize, block_size: usize) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+
+    for i in 0..size.div_ceil(block_size) {
+        let mut block = vec![0u8; block_size.min(size - i * block_size)];
+
+        // Alternate compressible and uncompressible blocks
+        if i % 3 == 0 {
+            block.fill(0);
+        } else if i % 3 == 1 {
+            for (j, b) in block.iter_mut().enumerate() {
+                *b = (j as u8).wrapping_add(i as u8);
+            }
+        } else {
+            getrandom::fill(&mut block).unwrap();
+        }
+
+        file.write_all(&block)?;
+    }
+
+    Ok(())
+}
@@ -1,11 +1,40 @@
 use std::env;
+use std::fs::File;
 use std::path::Path;
+use std::str::FromStr;
+
+use ciso_rs::{CisoFormat, ImageFormat, LegacyFormat, detect_format};
+
+/// Input or output path standing for stdin or stdout.
+pub const STDIO: &str = "-";
 
 #[derive(Debug)]
 pub enum Mode {
-    Compress { level: u32 },
-    Decompress,
-    Check { full: bool },
+    Compress {
+        level: u32,
+        block_size: Option<u32>,
+        align: Option<u8>,
+        threads: Option<usize>,
+        format: CisoFormat,
+    },
+    Recompress {
+        level: Option<u32>, // Unless given, the blocks the output can hold are copied as stored
+        block_size: Option<u32>,
+        align: Option<u8>,
+        threads: Option<usize>,
+        format: CisoFormat,
+    },
+    Decompress {
+        threads: Option<usize>,
+    },
+    Check {
+        full: bool,
+        threads: Option<usize>,
+        keep_going: bool, // Report every faulty block instead of the first one
+    },
+    Repair {
+        reference: Option<String>, // Image filling the lost blocks instead of zeros
+    },
 }
 
 #[derive(Debug)]
@@ -13,6 +42,8 @@ pub struct Args {
     pub mode: Mode,
     pub input: String,
     pub output: String,
+    pub format: Option<ImageFormat>, // Detected once while parsing, None when unknown
+    pub force: bool,                 // Overwrite an existing output
 }
 
 impl Args {
@@ -24,22 +55,100 @@ impl Args {
         }
 
         let input = args.remove(0);
-        let ext = Path::new(&input)
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-
-        match ext.as_str() {
-            "iso" => Self::parse_compress(input, &args),
-            "cso" => Self::parse_decompress(input, &args),
-            _ => Err("Input must be .iso or .cso".to_string()),
+        let parsed = match input.as_str() {
+            "compress" | "decompress" if args.is_empty() => return Err(usage()),
+            "compress" => {
+                let input = args.remove(0);
+                let format = input_format(&input);
+                Self::parse_compress(input, format, &args)?
+            }
+            "decompress" => {
+                let input = args.remove(0);
+                let format = input_format(&input);
+                Self::parse_decompress(input, format, &args)?
+            }
+            _ => {
+                let format = input_format(&input);
+                match format {
+                    Some(ImageFormat::Iso) => Self::parse_compress(input, format, &args)?,
+                    Some(ImageFormat::Ciso(_)) => Self::parse_ciso(input, format, &args)?,
+                    Some(ImageFormat::Legacy(_)) => Self::parse_legacy(input, format, &args)?,
+                    None => {
+                        return Err(format!(
+                            "Cannot tell the format of {input}: expected an ISO9660, CSO, ZSO, \
+                             DAX or JSO image, or a .iso, .cso, .zso, .dax or .jso extension"
+                        ));
+                    }
+                }
+            }
+        };
+
+        // Misnamed inputs, e.g. a CSO named .iso, would get their own name as default output
+        if parsed.input != STDIO && parsed.input == parsed.output {
+            return Err(format!(
+                "The output would overwrite {}, give another output path",
+                parsed.input
+            ));
         }
+
+        Ok(parsed)
+    }
+
+    /// Whether the input is a DAX or JSO image, read through `LegacyReader`.
+    pub fn is_legacy(&self) -> bool {
+        matches!(self.format, Some(ImageFormat::Legacy(_)))
     }
 
-    fn parse_compress(input: String, args: &[String]) -> Result<Args, String> {
+    /// CSO and ZSO images are recompressed when given a .cso or .zso output or compression
+    /// options, decompressed otherwise.
+    fn parse_ciso(
+        input: String,
+        detected: Option<ImageFormat>,
+        args: &[String],
+    ) -> Result<Args, String> {
+        let recompress = args
+            .iter()
+            .any(|arg| COMPRESSION_OPTIONS.contains(&arg.as_str()))
+            || output_arg(args)
+                .is_some_and(|output| matches!(extension(output).as_str(), "cso" | "zso"));
+        if recompress {
+            Self::parse_compress(input, detected, args)
+        } else {
+            Self::parse_decompress(input, detected, args)
+        }
+    }
+
+    /// Legacy images are converted to CSO, decompressed into an .iso output, or checked.
+    fn parse_legacy(
+        input: String,
+        detected: Option<ImageFormat>,
+        args: &[String],
+    ) -> Result<Args, String> {
+        if args.iter().any(|arg| arg == "--repair") {
+            return Err("--repair only supports .cso and .zso inputs".to_string());
+        }
+
+        let expand = args.iter().any(|arg| arg == "--check")
+            || output_arg(args).is_some_and(|output| extension(output) == "iso");
+        if expand {
+            Self::parse_decompress(input, detected, args)
+        } else {
+            Self::parse_compress(input, detected, args)
+        }
+    }
+
+    fn parse_compress(
+        input: String,
+        detected: Option<ImageFormat>,
+        args: &[String],
+    ) -> Result<Args, String> {
         let mut output = None;
         let mut level = None;
+        let mut block_size = None;
+        let mut align = None;
+        let mut threads = None;
+        let mut format = None;
+        let mut force = false;
 
         let mut i = 0;
         while i < args.len() {
@@ -56,17 +165,28 @@ impl Args {
                     level = Some(9);
                     i += 1;
                 }
+                "--force" => {
+                    force = true;
+                    i += 1;
+                }
                 "--level" => {
-                    if i + 1 >= args.len() {
-                        return Err("--level requires a value".to_string());
-                    }
-                    let v = args[i + 1]
-                        .parse::<u32>()
-                        .map_err(|_| "Invalid --level value")?;
-                    if !(1..=9).contains(&v) {
-                        return Err("--level must be 1..9".to_string());
-                    }
-                    level = Some(v);
+                    level = Some(option_value::<u32>(args, i)?);
+                    i += 2;
+                }
+                "--block-size" => {
+                    block_size = Some(option_value(args, i)?);
+                    i += 2;
+                }
+                "--align" => {
+                    align = Some(option_value(args, i)?);
+                    i += 2;
+                }
+                "--threads" => {
+                    threads = Some(option_value(args, i)?);
+                    i += 2;
+                }
+                "--format" => {
+                    format = Some(ciso_format(&option_value::<String>(args, i)?)?);
                     i += 2;
                 }
                 s if s.starts_with("--") => {
@@ -82,58 +202,236 @@ impl Args {
             }
         }
 
-        let output = output.unwrap_or_else(|| default_out(&input, "cso"));
-        let level = level.unwrap_or(6);
+        if input == STDIO && output.is_none() {
+            return Err("An output is required when compressing stdin".to_string());
+        }
+
+        // Without --format, a .zso output selects ZSO
+        let format = format.unwrap_or_else(|| match output.as_deref().map(extension) {
+            Some(ext) if ext == "zso" => CisoFormat::Zso,
+            _ => CisoFormat::Cso,
+        });
+
+        let (ext, max_level) = match format {
+            CisoFormat::Cso | CisoFormat::Cso2 => ("cso", 9),
+            CisoFormat::Zso => ("zso", 12),
+        };
+        if level.is_some_and(|level| !(1..=max_level).contains(&level)) {
+            return Err(format!("--level must be 1..{max_level}"));
+        }
+
+        let output = output.unwrap_or_else(|| default_out(&input, ext));
+
+        // CSO and ZSO inputs are converted without going through a plain image
+        let mode = if matches!(detected, Some(ImageFormat::Ciso(_))) {
+            Mode::Recompress {
+                level,
+                block_size,
+                align,
+                threads,
+                format,
+            }
+        } else {
+            Mode::Compress {
+                level: level.unwrap_or(6),
+                block_size,
+                align,
+                threads,
+                format,
+            }
+        };
 
         Ok(Args {
-            mode: Mode::Compress { level },
+            mode,
             input,
             output,
+            format: detected,
+            force,
         })
     }
 
-    fn parse_decompress(input: String, args: &[String]) -> Result<Args, String> {
+    fn parse_decompress(
+        input: String,
+        detected: Option<ImageFormat>,
+        args: &[String],
+    ) -> Result<Args, String> {
+        if input == STDIO {
+            return Err(
+                "The input must be a .cso or .zso file, stdin cannot be decompressed".to_string(),
+            );
+        }
+
+        let mut output = None;
         let mut check = false;
         let mut full = false;
+        let mut keep_going = false;
+        let mut force = false;
+        let mut threads = None;
+        let mut repair = false;
+        let mut reference = None;
 
-        for arg in args {
-            match arg.as_str() {
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
                 "--check" => check = true,
                 "--full" => full = true,
-                _ => return Err(format!("Unknown option '{arg}'")),
+                "--keep-going" => keep_going = true,
+                "--force" => force = true,
+                "--repair" => repair = true,
+                "--threads" => {
+                    threads = Some(option_value(args, i)?);
+                    i += 1;
+                }
+                "--reference" => {
+                    reference = Some(option_value(args, i)?);
+                    i += 1;
+                }
+                arg if arg.starts_with("--") => return Err(format!("Unknown option '{arg}'")),
+                arg => {
+                    if output.is_some() {
+                        return Err("Too many positional arguments".to_string());
+                    }
+                    output = Some(arg.to_string());
+                }
             }
+            i += 1;
         }
 
-        if full && !check {
-            return Err("--full can only be used with --check".to_string());
+        if (full || keep_going) && !check {
+            return Err("--full and --keep-going can only be used with --check".to_string());
         }
 
-        if check {
-            if args.len() > 2 {
-                return Err("Too many arguments for --check".to_string());
+        if reference.is_some() && !repair {
+            return Err("--reference can only be used with --repair".to_string());
+        }
+
+        if repair {
+            if check || threads.is_some() {
+                return Err("--repair cannot be used with --check or --threads".to_string());
             }
+            if output.is_some() {
+                return Err(
+                    "--repair takes no output, it writes <input>.repaired.cso or .zso".to_string(),
+                );
+            }
+
+            // The repaired image keeps the format of the input
+            let output = if detected == Some(ImageFormat::Ciso(CisoFormat::Zso)) {
+                default_out(&input, "repaired.zso")
+            } else {
+                default_out(&input, "repaired.cso")
+            };
 
             return Ok(Args {
-                mode: Mode::Check { full },
+                mode: Mode::Repair { reference },
                 input,
-                output: String::new(),
+                output,
+                format: detected,
+                force,
             });
         }
 
-        if !args.is_empty() {
-            return Err("Options are not allowed when decompressing".to_string());
+        if check {
+            if force {
+                return Err("--force cannot be used with --check".to_string());
+            }
+            if output.is_some() {
+                return Err("--check takes no output".to_string());
+            }
+
+            return Ok(Args {
+                mode: Mode::Check {
+                    full,
+                    threads,
+                    keep_going,
+                },
+                input,
+                output: String::new(),
+                format: detected,
+                force,
+            });
         }
 
-        let output = default_out(&input, "iso");
+        let output = output.unwrap_or_else(|| default_out(&input, "iso"));
 
         Ok(Args {
-            mode: Mode::Decompress,
+            mode: Mode::Decompress { threads },
             input,
             output,
+            format: detected,
+            force,
         })
     }
 }
 
+/// Options selecting compression on inputs which can also be decompressed.
+const COMPRESSION_OPTIONS: [&str; 7] = [
+    "--level",
+    "--fast",
+    "--optimal",
+    "--best",
+    "--block-size",
+    "--align",
+    "--format",
+];
+
+/// First positional argument, skipping the values of the options taking one.
+fn output_arg(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" | "--block-size" | "--align" | "--threads" | "--format" | "--reference" => {
+                args.next();
+            }
+            arg if arg.starts_with("--") => {}
+            arg => return Some(arg),
+        }
+    }
+    None
+}
+
+fn ciso_format(name: &str) -> Result<CisoFormat, String> {
+    match name {
+        "cso" | "cso1" => Ok(CisoFormat::Cso),
+        "cso2" => Ok(CisoFormat::Cso2),
+        "zso" => Ok(CisoFormat::Zso),
+        other => Err(format!("Unknown format '{other}'")),
+    }
+}
+
+fn option_value<T: FromStr>(args: &[String], i: usize) -> Result<T, String> {
+    let name = &args[i];
+    let value = args
+        .get(i + 1)
+        .ok_or_else(|| format!("{name} requires a value"))?;
+    value.parse().map_err(|_| format!("Invalid {name} value"))
+}
+
+/// Format of `path` as told by its content, or by its extension when the content is unknown.
+fn input_format(path: &str) -> Option<ImageFormat> {
+    let detected = File::open(path)
+        .ok()
+        .and_then(|file| detect_format(file).ok().flatten());
+
+    detected.or_else(|| match extension(path).as_str() {
+        "iso" => Some(ImageFormat::Iso),
+        "cso" => Some(ImageFormat::Ciso(CisoFormat::Cso)),
+        "zso" => Some(ImageFormat::Ciso(CisoFormat::Zso)),
+        "dax" => Some(ImageFormat::Legacy(LegacyFormat::Dax)),
+        "jso" => Some(ImageFormat::Legacy(LegacyFormat::Jso)),
+        _ => None,
+    })
+}
+
+/// Lowercase extension of `path`, empty when it has none.
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
 fn default_out(input: &str, ext: &str) -> String {
     let path = Path::new(input);
     let stem = path.file_stem().unwrap_or_default().to_string_lossy();
@@ -146,16 +444,33 @@ fn default_out(input: &str, ext: &str) -> String {
 
 fn usage() -> String {
     r"Usage:
-  ciso <input.iso> [output.cso] [--level 1..9 | --fast | --optimal | --best]
-  ciso <input.cso> [output.iso]
-  ciso <input.cso> --check [--full]
+  ciso <input.iso> [output.cso|zso] [--level 1..9 | --fast | --optimal | --best]
+       [--block-size <bytes>] [--align <shift>] [--threads <n>] [--format cso|cso2|zso] [--force]
+  ciso <input.cso|zso> [output.iso] [--threads <n>] [--force]
+  ciso <input.cso|zso> --check [--full] [--keep-going] [--threads <n>]
+  ciso <input.cso|zso> --repair [--reference <image.iso>] [--force]
+  ciso <input.cso|zso> <output.cso|zso> [compression options]
+  ciso compress <input|-> [output.cso|zso|-] [compression options]
+  ciso decompress <input.cso|zso> [output.iso|-] [--threads <n>] [--force]
+  ciso <input.dax|jso> [output.cso|zso|-] [compression options]
+  ciso <input.dax|jso> <output.iso> [--force]
+  ciso <input.dax|jso> --check [--full] [--keep-going]
 
 Rules:
-  .iso → compress
-  .cso → decompress
+  the input format is detected from its content, the extension only being used when it is unknown
+  ISO9660 image (.iso) → compress
+  CSO, ZSO (.cso, .zso) → decompress, or recompress given a .cso / .zso output or compression options
+  DAX, JSO (.dax, .jso) → convert to CSO, or decompress to an .iso output
+  - reads stdin or writes stdout, piped images are compressed on a single thread
+  --repair writes <input>.repaired.cso (or .zso), lost blocks are zero-filled unless --reference is given
+  --format cso2 writes CSO v2, each block stored with the smaller of deflate and LZ4
+  --format zso, or a .zso output, writes ZSO: LZ4 blocks, --level 1..12 being the LZ4 HC level
+  recompression copies the blocks the output can hold as stored, a --level encodes them all again
+  existing outputs are only overwritten with --force
 
 Defaults:
-  compress level = 6 (--optimal)
+  compress level = 6 (--optimal), format = cso (v1, deflate only)
+  block size = 2048, align = auto (smallest fitting the image), threads = number of CPUs
 "
     .to_string()
 }
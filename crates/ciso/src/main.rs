@@ -1,16 +1,33 @@
 use std::fs::File;
-use std::io;
+use std::io::{self, Read};
 use std::process;
+use std::sync::Arc;
 
-use ciso_rs::check_ciso;
-use ciso_rs::compress_ciso;
-use ciso_rs::decompress_ciso;
+use ciso_rs::check_ciso_report;
+use ciso_rs::check_ciso_with;
+use ciso_rs::check_legacy;
+use ciso_rs::compress_ciso_spooled;
+use ciso_rs::compress_ciso_with;
+use ciso_rs::convert_legacy;
+use ciso_rs::decompress_ciso_with;
+use ciso_rs::decompress_legacy;
+use ciso_rs::recompress_ciso;
+use ciso_rs::repair_ciso;
+use ciso_rs::{
+    CancellationToken, CheckOptions, CheckReport, CisoError, CisoFormat, CisoReader,
+    CompressOptions, DecompressOptions, FaultyBlock, LegacyReader, LegacyReport, RecompressOptions,
+    RepairOptions, RepairReport,
+};
 
-use crate::args::{Args, Mode};
+use crate::args::{Args, Mode, STDIO};
+use crate::output::AtomicOutput;
+use crate::progress::{Direction, ProgressBar};
 
 mod args;
+mod output;
+mod progress;
 
-fn main() -> io::Result<()> {
+fn main() {
     let args = match Args::parse() {
         Ok(args) => args,
         Err(err) => {
@@ -19,34 +36,399 @@ fn main() -> io::Result<()> {
         }
     };
 
+    let cancel = CancellationToken::new();
+    {
+        let cancel = cancel.clone();
+        if let Err(err) = ctrlc::set_handler(move || cancel.cancel()) {
+            eprintln!("Failed to install the Ctrl-C handler: {err}");
+        }
+    }
+
+    match run(&args, &cancel) {
+        Ok(()) => {}
+        Err(CisoError::Cancelled) => {
+            eprintln!("\nCancelled");
+            process::exit(130);
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+fn run(args: &Args, cancel: &CancellationToken) -> Result<(), CisoError> {
     match args.mode {
-        Mode::Compress { level } => {
-            println!(
-                "Compress {} → {} (level {})",
-                args.input, args.output, level
+        Mode::Compress {
+            level,
+            block_size,
+            align,
+            threads,
+            format,
+        } => {
+            let bar = Arc::new(ProgressBar::new(Direction::Compress));
+            let options = compress_options(Some(level), block_size, align, threads, format)
+                .progress(bar.observer())
+                .cancellation(cancel.clone());
+            options.validate()?;
+
+            announce(
+                args,
+                &format!(
+                    "Compress {} → {} (level {})",
+                    args.input, args.output, level
+                ),
             );
 
-            let input = File::open(&args.input)?;
-            let output = File::create(&args.output)?;
+            compress(args, &options)?;
+            bar.finish();
+        }
+        Mode::Recompress {
+            level,
+            block_size,
+            align,
+            threads,
+            format,
+        } => {
+            let bar = Arc::new(ProgressBar::new(Direction::Compress));
+            let options = compress_options(level, block_size, align, threads, format)
+                .progress(bar.observer())
+                .cancellation(cancel.clone());
+            options.validate()?;
+
+            let shown_level = level.map(|level| format!(" (level {level})"));
+            announce(
+                args,
+                &format!(
+                    "Recompress {} → {}{}",
+                    args.input,
+                    args.output,
+                    shown_level.unwrap_or_default()
+                ),
+            );
 
-            compress_ciso(input, output, level)?;
+            recompress(args, options, &bar)?;
         }
-        Mode::Decompress => {
-            println!("Decompress {} → {}", args.input, args.output);
+        Mode::Decompress { threads } => {
+            let bar = Arc::new(ProgressBar::new(Direction::Expand));
+            let mut options = DecompressOptions::new()
+                .progress(bar.observer())
+                .cancellation(cancel.clone());
+            if let Some(threads) = threads {
+                options = options.threads(threads);
+            }
+            options.validate()?;
 
-            let input = File::open(&args.input)?;
-            let output = File::create(&args.output)?;
+            announce(
+                args,
+                &format!("Decompress {} → {}", args.input, args.output),
+            );
 
-            decompress_ciso(input, output)?;
+            decompress(args, &options)?;
+            bar.finish();
         }
-        Mode::Check { full } => {
+        Mode::Check {
+            full,
+            threads,
+            keep_going,
+        } => {
+            let bar = Arc::new(ProgressBar::new(Direction::Expand));
+            let mut options = CheckOptions::new()
+                .full(full)
+                .progress(bar.observer())
+                .cancellation(cancel.clone());
+            if let Some(threads) = threads {
+                options = options.threads(threads);
+            }
+            options.validate()?;
+
             println!("Check {}", args.input);
 
+            check(args, &options, keep_going, &bar)?;
+        }
+        Mode::Repair { ref reference } => {
+            let bar = Arc::new(ProgressBar::new(Direction::Compress));
+            let mut options = RepairOptions::new()
+                .progress(bar.observer())
+                .cancellation(cancel.clone());
+            if let Some(reference) = reference {
+                options = options.reference(File::open(reference)?);
+            }
+
+            println!("Repair {} → {}", args.input, args.output);
+
             let input = File::open(&args.input)?;
+            let estimated_size = input.metadata()?.len();
+            let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
 
-            check_ciso(input, full)?;
+            let report = repair_ciso(input, output.file()?, &options)?;
+            output.commit()?;
+            bar.finish();
+            print_repair_report(&report);
         }
     }
 
     Ok(())
 }
+
+/// Options from the command line, the library defaults standing for those not given.
+fn compress_options(
+    level: Option<u32>,
+    block_size: Option<u32>,
+    align: Option<u8>,
+    threads: Option<usize>,
+    format: CisoFormat,
+) -> CompressOptions {
+    let mut options = CompressOptions::new().format(format);
+    if let Some(level) = level {
+        options = options.level(level);
+    }
+    if let Some(block_size) = block_size {
+        options = options.block_size(block_size);
+    }
+    if let Some(align) = align {
+        options = options.align(align);
+    }
+    if let Some(threads) = threads {
+        options = options.threads(threads);
+    }
+    options
+}
+
+/// Status line, kept off stdout when it carries the output image.
+fn announce(args: &Args, line: &str) {
+    if args.output == STDIO {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// Compression between files, or from stdin / to stdout through a temporary spool file.
+fn compress(args: &Args, options: &CompressOptions) -> Result<(), CisoError> {
+    if args.is_legacy() {
+        return convert(args, options);
+    }
+
+    if args.input != STDIO && args.output != STDIO {
+        let input = File::open(&args.input)?;
+        let estimated_size = options.max_output_size(input.metadata()?.len());
+        let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
+
+        compress_ciso_with(input, output.file()?, options)?;
+        return Ok(output.commit()?);
+    }
+
+    let input: Box<dyn Read> = if args.input == STDIO {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(&args.input)?)
+    };
+    let spool = tempfile::tempfile()?;
+
+    if args.output == STDIO {
+        return compress_ciso_spooled(input, io::stdout().lock(), spool, options);
+    }
+
+    // The input size is unknown, only an existing output is refused upfront
+    let output = AtomicOutput::create(&args.output, args.force, 0)?;
+    compress_ciso_spooled(input, output.file()?, spool, options)?;
+    Ok(output.commit()?)
+}
+
+/// Conversion of a CSO or ZSO image into another one, reporting how many blocks were copied.
+///
+/// Written to stdout, every block is encoded again through a spool file.
+fn recompress(args: &Args, options: CompressOptions, bar: &ProgressBar) -> Result<(), CisoError> {
+    let input = File::open(&args.input)?;
+    if args.output == STDIO {
+        let spool = tempfile::tempfile()?;
+        compress_ciso_spooled(
+            CisoReader::new(input)?,
+            io::stdout().lock(),
+            spool,
+            &options,
+        )?;
+        bar.finish();
+        return Ok(());
+    }
+
+    let estimated_size = options.max_output_size(CisoReader::new(&input)?.len());
+    let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
+
+    let options = RecompressOptions::new().compress(options);
+    let report = recompress_ciso(input, output.file()?, &options)?;
+    output.commit()?;
+    bar.finish();
+
+    announce(
+        args,
+        &format!(
+            "Copied {} of {} blocks as stored",
+            report.copied_blocks, report.total_blocks
+        ),
+    );
+    Ok(())
+}
+
+/// DAX or JSO conversion to CSO, spooled when writing to stdout since the index comes first.
+fn convert(args: &Args, options: &CompressOptions) -> Result<(), CisoError> {
+    let input = File::open(&args.input)?;
+    if args.output == STDIO {
+        let spool = tempfile::tempfile()?;
+        return compress_ciso_spooled(
+            LegacyReader::new(input)?,
+            io::stdout().lock(),
+            spool,
+            options,
+        );
+    }
+
+    let estimated_size = options.max_output_size(LegacyReader::new(&input)?.len());
+    let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
+
+    convert_legacy(input, output.file()?, options)?;
+    Ok(output.commit()?)
+}
+
+/// Decompression to a file or stdout.
+fn decompress(args: &Args, options: &DecompressOptions) -> Result<(), CisoError> {
+    let input = File::open(&args.input)?;
+    if args.is_legacy() {
+        if args.output == STDIO {
+            return decompress_legacy(input, io::stdout().lock(), options);
+        }
+
+        let estimated_size = LegacyReader::new(&input)?.len();
+        let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
+
+        decompress_legacy(input, output.file()?, options)?;
+        return Ok(output.commit()?);
+    }
+
+    if args.output == STDIO {
+        return decompress_ciso_with(input, io::stdout().lock(), options);
+    }
+
+    let estimated_size = CisoReader::new(&input)?.len();
+    let output = AtomicOutput::create(&args.output, args.force, estimated_size)?;
+
+    decompress_ciso_with(input, output.file()?, options)?;
+    Ok(output.commit()?)
+}
+
+/// Check of the input, listing every faulty block with `keep_going` instead of stopping at the first.
+fn check(
+    args: &Args,
+    options: &CheckOptions,
+    keep_going: bool,
+    bar: &ProgressBar,
+) -> Result<(), CisoError> {
+    let input = File::open(&args.input)?;
+
+    if args.is_legacy() {
+        let report = check_legacy(input, options)?;
+        bar.finish();
+        if keep_going {
+            print_legacy_report(&report);
+            if !report.is_ok() {
+                process::exit(1);
+            }
+        } else if let Some(faulty) = report.faulty_blocks.into_iter().next() {
+            return Err(faulty.error);
+        }
+        return Ok(());
+    }
+
+    if keep_going {
+        let report = check_ciso_report(input, options)?;
+        bar.finish();
+        print_report(&report);
+        if !report.is_ok() {
+            process::exit(1);
+        }
+    } else {
+        check_ciso_with(input, options)?;
+        bar.finish();
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &CheckReport) {
+    let header = &report.header;
+
+    println!(
+        "Image:    {} bytes, block size {}, align {}, version {} ({:?})",
+        header.total_bytes,
+        header.block_size,
+        header.align,
+        header.ver,
+        header.format()
+    );
+    println!(
+        "Blocks:   {} ({} plain, {} compressed)",
+        report.total_blocks, report.plain_blocks, report.compressed_blocks
+    );
+    println!(
+        "CSO:      {} bytes, ratio {:.1}%, {} trailing bytes",
+        report.file_len,
+        report.ratio() * 100.0,
+        report.trailing_bytes
+    );
+
+    print_faulty_blocks(&report.faulty_blocks);
+}
+
+fn print_legacy_report(report: &LegacyReport) {
+    println!(
+        "Image:    {} bytes, block size {} ({:?})",
+        report.total_bytes, report.block_size, report.format
+    );
+    println!(
+        "Blocks:   {} ({} plain, {} compressed)",
+        report.total_blocks, report.plain_blocks, report.compressed_blocks
+    );
+    println!(
+        "File:     {} bytes, ratio {:.1}%",
+        report.file_len,
+        report.ratio() * 100.0
+    );
+
+    print_faulty_blocks(&report.faulty_blocks);
+}
+
+fn print_faulty_blocks(faulty_blocks: &[FaultyBlock]) {
+    if faulty_blocks.is_empty() {
+        println!("No faulty block");
+        return;
+    }
+
+    println!("Faulty blocks: {}", faulty_blocks.len());
+    for faulty in faulty_blocks {
+        println!(
+            "  block {} (image bytes {:#x}..{:#x}): {}",
+            faulty.block, faulty.range.start, faulty.range.end, faulty.error
+        );
+    }
+}
+
+fn print_repair_report(report: &RepairReport) {
+    if report.index_rebuilt {
+        println!("Index was damaged, rebuilt from the block data");
+    }
+
+    if report.is_complete() {
+        println!("All {} blocks recovered", report.total_blocks);
+        return;
+    }
+
+    println!(
+        "Lost blocks: {} of {}",
+        report.lost_blocks.len(),
+        report.total_blocks
+    );
+    for sectors in &report.lost_sectors {
+        println!("  LBA {}..{}", sectors.start, sectors.end);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+#[cfg(unix)]
+use std::fs::Permissions;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+/// Output written to a temporary file next to its destination, and only renamed into place by `commit`.
+///
+/// Dropping it before that, e.g. on error or cancellation, removes the temporary file.
+pub struct AtomicOutput {
+    path: PathBuf,
+    tmp: NamedTempFile,
+    force: bool,
+}
+
+impl AtomicOutput {
+    /// Refuses existing outputs unless `force` is set, and outputs larger than the free space left.
+    pub fn create(path: &str, force: bool, estimated_size: u64) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if !force && path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists, use --force to overwrite it",
+                    path.display()
+                ),
+            ));
+        }
+
+        let dir = parent_dir(&path);
+        let available = fs4::available_space(dir)?;
+        if available < estimated_size {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "not enough space in {}: {estimated_size} bytes needed, {available} available",
+                    dir.display()
+                ),
+            ));
+        }
+
+        let prefix = format!(".{}.", path.file_name().unwrap_or_default().display());
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&prefix).suffix(".tmp");
+        // Temporary files are private by default, give the output the usual permissions
+        #[cfg(unix)]
+        builder.permissions(Permissions::from_mode(0o666));
+        let tmp = builder.tempfile_in(dir)?;
+
+        Ok(Self { path, tmp, force })
+    }
+
+    pub fn file(&self) -> io::Result<File> {
+        self.tmp.as_file().try_clone()
+    }
+
+    /// Flushes the data to disk and moves the file to its destination.
+    pub fn commit(self) -> io::Result<()> {
+        self.tmp.as_file().sync_all()?;
+
+        if self.force {
+            self.tmp.persist(&self.path)?;
+        } else {
+            // Don't clobber an output created while we were running
+            self.tmp.persist_noclobber(&self.path)?;
+        }
+
+        // Make the rename itself durable
+        #[cfg(unix)]
+        File::open(parent_dir(&self.path))?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
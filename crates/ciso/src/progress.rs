@@ -0,0 +1,116 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ciso_rs::Progress;
+
+const BAR_WIDTH: usize = 30;
+const REFRESH: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Compress, // Plain input, compressed output
+    Expand,   // Compressed input, plain output
+}
+
+pub struct ProgressBar {
+    direction: Direction,
+    start: Instant,
+    last_draw: Mutex<Option<Instant>>,
+    enabled: bool,
+}
+
+impl ProgressBar {
+    pub fn new(direction: Direction) -> Self {
+        Self {
+            direction,
+            start: Instant::now(),
+            last_draw: Mutex::new(None),
+            enabled: io::stderr().is_terminal(),
+        }
+    }
+
+    #[expect(clippy::cast_precision_loss)]
+    #[expect(clippy::cast_possible_truncation)]
+    #[expect(clippy::cast_sign_loss)]
+    pub fn update(&self, progress: &Progress) {
+        if !self.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let done = progress.blocks_done == progress.total_blocks;
+        {
+            let mut last_draw = self.last_draw.lock().unwrap();
+            if !done && last_draw.is_some_and(|last| now - last < REFRESH) {
+                return;
+            }
+            *last_draw = Some(now);
+        }
+
+        let (plain, stored) = match self.direction {
+            Direction::Compress => (progress.bytes_in, progress.bytes_out),
+            Direction::Expand => (progress.bytes_out, progress.bytes_in),
+        };
+
+        let elapsed = (now - self.start).as_secs_f64();
+        let throughput = plain as f64 / elapsed.max(f64::EPSILON) / (1024.0 * 1024.0);
+        let ratio = if plain == 0 {
+            100.0
+        } else {
+            stored as f64 * 100.0 / plain as f64
+        };
+
+        let mut stderr = io::stderr().lock();
+
+        // Piped inputs have no known size until they are exhausted
+        if progress.total_blocks == 0 && progress.blocks_done > 0 {
+            let _ = write!(
+                stderr,
+                "\r{:10.1} MiB  {:8.1} MiB/s  ratio {:5.1}% ",
+                plain as f64 / (1024.0 * 1024.0),
+                throughput,
+                ratio,
+            );
+            let _ = stderr.flush();
+            return;
+        }
+
+        let fraction = if progress.total_blocks == 0 {
+            1.0
+        } else {
+            progress.blocks_done as f64 / progress.total_blocks as f64
+        };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+
+        let eta = if fraction > 0.0 {
+            (elapsed * (1.0 - fraction) / fraction) as u64
+        } else {
+            0
+        };
+
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:5.1}%  {:8.1} MiB/s  ratio {:5.1}%  ETA {:02}:{:02} ",
+            "#".repeat(filled),
+            ".".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            throughput,
+            ratio,
+            eta / 60,
+            eta % 60,
+        );
+        let _ = stderr.flush();
+    }
+
+    pub fn observer(self: &Arc<Self>) -> impl Fn(&Progress) + Send + Sync + 'static {
+        let bar = self.clone();
+        move |progress| bar.update(progress)
+    }
+
+    pub fn finish(&self) {
+        if self.enabled && self.last_draw.lock().unwrap().is_some() {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,18 @@
+#![no_main]
+
+use ciso_rs::CisoHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(bytes) = data.first_chunk::<{ CisoHeader::SIZE }>() {
+        let header = CisoHeader::from_bytes(bytes);
+        assert_eq!(header.to_bytes(), *bytes);
+
+        if header.validate().is_ok() {
+            let _ = header.total_blocks();
+            assert!(CisoHeader::min_align(header.total_bytes, header.block_size) <= 31);
+        }
+    }
+
+    let _ = CisoHeader::read_from(&mut &data[..]);
+});
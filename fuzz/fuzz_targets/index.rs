@@ -0,0 +1,27 @@
+#![no_main]
+
+use ciso_rs::{CisoHeader, CisoIndex};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let file_len = data.len() as u64;
+    let mut bytes = data;
+
+    // Parses the header and the index, bounded by the input length
+    let Ok(header) = CisoHeader::read_from(&mut bytes) else {
+        return;
+    };
+    let Ok(index) = CisoIndex::read_from(&mut bytes, &header, file_len) else {
+        return;
+    };
+
+    // Entries only have to be ordered once validated, the accessors must not panic either way
+    if index.validate(file_len).is_ok() {
+        for entry in index.iter() {
+            assert!(entry.offset <= index.offset(index.len()) || entry.stored_len == 0);
+        }
+    } else {
+        index.iter().for_each(drop);
+    }
+    let _ = index.block_for_offset(header.total_bytes.saturating_sub(1));
+});
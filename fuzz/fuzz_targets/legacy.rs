@@ -0,0 +1,15 @@
+#![no_main]
+
+use std::io::{self, Cursor, Read};
+
+use ciso_rs::LegacyReader;
+use libfuzzer_sys::fuzz_target;
+
+/// Decompressed bytes read per input, a tiny index can still describe a huge image
+const MAX_OUTPUT: u64 = 16 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(reader) = LegacyReader::new(Cursor::new(data)) {
+        let _ = io::copy(&mut reader.take(MAX_OUTPUT), &mut io::sink());
+    }
+});